@@ -1,4 +1,5 @@
-use clap::{App, Arg};
+use crate::{clean, report};
+use clap::{App, AppSettings, Arg, SubCommand};
 
 const ABOUT: &str = "
 enforcer is a utility to help you keep your source code in a more consistent state.
@@ -14,7 +15,9 @@ const USAGE: &str = "
     enforcer [OPTIONS] [-g ENDINGS...] <path>
     enforcer [-g ENDINGS...] [-q | --quiet] [-j <NUM> | --threads=<NUM>] [-a | --color] <path>
     enforcer [-c | --clean] <path>
-    enforcer [-l <MAX> | --length=<MAX>] <path>";
+    enforcer [-l <MAX> | --length=<MAX>] <path>
+    enforcer config migrate [--check] [<path>]
+    enforcer init [--force] [<path>]";
 
 const TEMPLATE: &str = "\
 {bin} {version}
@@ -27,7 +30,10 @@ ARGS:
 {positionals}
 
 OPTIONS:
-{unified}";
+{unified}
+
+SUBCOMMANDS:
+{subcommands}";
 
 pub fn app() -> App<'static, 'static> {
     App::new("enforcer")
@@ -59,9 +65,68 @@ pub fn app() -> App<'static, 'static> {
             Arg::with_name("clean")
                 .short("c")
                 .long("clean")
-                .help("clean up trailing whitespaces and convert tabs to spaces")
+                .help("clean up trailing whitespaces and convert tabs to spaces (leading spaces to tabs with --tabs)")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .value_name("FORMAT")
+                .help("output format of the report")
+                .possible_values(report::Format::NAMES)
+                .default_value("text")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("staged")
+                .long("staged")
+                .help("only check files staged in git, using their staged content (pre-commit)")
+                .conflicts_with("clean")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("diff-base")
+                .long("diff-base")
+                .value_name("REF")
+                .help("only report findings on lines that changed compared to this git ref")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("clean-changed-only")
+                .long("clean-changed-only")
+                .help("with --diff-base: only clean the changed lines")
+                .requires_all(&["diff-base", "clean"])
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("no-ignore-files")
+                .long("no-ignore-files")
+                .help("do not honor .gitignore, .ignore and .enforcerignore files")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("files-from")
+                .long("files-from")
+                .value_name("FILE")
+                .help("check the files listed in FILE (- for stdin), one per line or NUL-separated")
+                .conflicts_with_all(&["path", "staged"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("stdin")
+                .long("stdin")
+                .help("check the content piped on stdin, with --clean the cleaned content is written to stdout")
+                .conflicts_with_all(&["path", "files-from", "staged", "diff-base"])
                 .takes_value(false),
         )
+        .arg(
+            Arg::with_name("stdin-filename")
+                .long("stdin-filename")
+                .value_name("NAME")
+                .help("file name used for the content on stdin (config selection and messages)")
+                .requires("stdin")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("config-path")
                 .short("f")
@@ -70,6 +135,12 @@ pub fn app() -> App<'static, 'static> {
                 .help("path to configuration file")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("config-fallback")
+                .long("config-fallback")
+                .help("take the default configuration if a config file is missing or broken")
+                .takes_value(false),
+        )
         .arg(
             Arg::with_name("status")
                 .short("s")
@@ -96,7 +167,13 @@ pub fn app() -> App<'static, 'static> {
             Arg::with_name("tabs")
                 .short("t")
                 .long("tabs")
-                .help("leave tabs alone (without that tabs are considered wrong)")
+                .help("allow tabs (without that tabs are considered wrong), with --clean indent with tabs")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("editorconfig")
+                .long("editorconfig")
+                .help("take the rules from the matching .editorconfig sections")
                 .takes_value(false),
         )
         .arg(
@@ -106,6 +183,15 @@ pub fn app() -> App<'static, 'static> {
                 .help("force windows line ending (CRLF)")
                 .takes_value(false),
         )
+        .arg(
+            Arg::with_name("line-ending")
+                .long("line-ending")
+                .value_name("POLICY")
+                .help("line ending of all lines, consistent takes the one most lines of a file use")
+                .possible_values(clean::LineEnding::NAMES)
+                .conflicts_with("use_crlf")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("L")
                 .value_name("MAX")
@@ -115,16 +201,56 @@ pub fn app() -> App<'static, 'static> {
                 .help("max line length [not checked if empty]")
                 .validator(validate_number),
         )
+        .arg(
+            Arg::with_name("tab_width")
+                .value_name("WIDTH")
+                .takes_value(true)
+                .long("tab-width")
+                .help("number of columns a tab expands to [default: 4 or as configured]")
+                .validator(validate_tab_width),
+        )
         .arg(
             Arg::with_name("N")
                 .value_name("NUM")
                 .takes_value(true)
                 .short("j")
                 .long("threads")
-                .default_value("4")
-                .help("number of threads")
+                .help("number of threads, 0 for one per CPU [default: 4 or as configured]")
                 .validator(validate_number),
         )
+        .subcommand(
+            SubCommand::with_name("config")
+                .about("work on the .enforcer files")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("migrate")
+                        .about("rewrite old style ignore patterns (`.git` => `**/.git`)")
+                        .arg(
+                            Arg::with_name("check")
+                                .long("check")
+                                .help("only show the changes, fail if there are any (for CI)")
+                                .takes_value(false),
+                        )
+                        .arg(
+                            Arg::with_name("path")
+                                .help("config file or directory whose .enforcer files are migrated"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("init")
+                .about("write a .enforcer file that fits the files found in a directory")
+                .arg(
+                    Arg::with_name("force")
+                        .long("force")
+                        .help("overwrite an existing .enforcer file")
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::with_name("path")
+                        .help("directory to scan and write the .enforcer file to"),
+                ),
+        )
 }
 
 fn validate_number(s: String) -> Result<(), String> {
@@ -132,3 +258,11 @@ fn validate_number(s: String) -> Result<(), String> {
         .map(|_| ())
         .map_err(|err| err.to_string())
 }
+
+fn validate_tab_width(s: String) -> Result<(), String> {
+    match s.parse::<usize>() {
+        Ok(0) => Err(String::from("tab width needs to be at least 1")),
+        Ok(_) => Ok(()),
+        Err(err) => Err(err.to_string()),
+    }
+}
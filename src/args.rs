@@ -1,11 +1,8 @@
-use std::{cmp, num, ops, process};
+use std::{num, ops, process};
 
 use crate::app;
-use clap;
-use env_logger;
-use num_cpus;
 
-use super::check::InfoLevel;
+use super::{check::InfoLevel, clean::LineEnding, report::Format};
 use std::{
     path::{Path, PathBuf},
     result::Result,
@@ -14,18 +11,40 @@ use std::{
 /// `Args` are transformed/normalized from `ArgMatches`.
 #[derive(Debug)]
 pub struct Args {
-    path: PathBuf,
+    paths: Vec<PathBuf>,
     endings: Vec<String>,
     clean: bool,
     config_file: Option<PathBuf>,
+    config_fallback: bool,
     line_length: Option<usize>,
+    tab_width: Option<usize>,
     color: bool,
-    threads: usize,
+    threads: Option<usize>,
     quiet: bool,
     use_crlf: bool,
+    line_ending: Option<LineEnding>,
+    editorconfig: bool,
     status: bool,
     tabs: bool,
+    staged: bool,
+    files_from: Option<PathBuf>,
+    stdin: bool,
+    stdin_filename: Option<PathBuf>,
+    use_ignore_files: bool,
+    diff_base: Option<String>,
+    clean_changed_only: bool,
     info_level: InfoLevel,
+    format: Format,
+    command: Option<Command>,
+}
+
+/// A subcommand that is run instead of checking files.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    /// rewrite old style ignore patterns of the config files (at `path`)
+    Migrate { path: PathBuf, check: bool },
+    /// write a config file for the files found in the directory `path`
+    Init { path: PathBuf, force: bool },
 }
 
 fn init_logging(is_debug: bool) {
@@ -78,9 +97,9 @@ impl Args {
     pub fn quiet(&self) -> bool {
         self.quiet
     }
-    /// Return the path that should be searched.
-    pub fn path(&self) -> &PathBuf {
-        &self.path
+    /// Return the paths (directories or files) that should be checked.
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
     }
     pub fn clean(&self) -> bool {
         self.clean
@@ -91,9 +110,48 @@ impl Args {
     pub fn tabs(&self) -> bool {
         self.tabs
     }
+    /// Whether only the files staged in git should be checked.
+    pub fn staged(&self) -> bool {
+        self.staged
+    }
+    /// File with the list of files to check (`-` means stdin).
+    pub fn files_from(&self) -> Option<&Path> {
+        self.files_from.as_deref()
+    }
+    /// Whether the content to check is read from stdin.
+    pub fn stdin(&self) -> bool {
+        self.stdin
+    }
+    /// The file name to use for the content read from stdin.
+    pub fn stdin_filename(&self) -> Option<&Path> {
+        self.stdin_filename.as_deref()
+    }
+    /// Whether .gitignore/.ignore/.enforcerignore files should be honored.
+    pub fn use_ignore_files(&self) -> bool {
+        self.use_ignore_files
+    }
+    /// The git ref to compare against, only changed lines are checked.
+    pub fn diff_base(&self) -> Option<&str> {
+        self.diff_base.as_deref()
+    }
+    pub fn clean_changed_only(&self) -> bool {
+        self.clean_changed_only
+    }
     pub fn use_crlf(&self) -> bool {
         self.use_crlf
     }
+    /// The line ending policy given on the command line.
+    pub fn line_ending(&self) -> Option<LineEnding> {
+        self.line_ending
+    }
+    /// Whether the rules are taken from .editorconfig files.
+    pub fn editorconfig(&self) -> bool {
+        self.editorconfig
+    }
+    /// Whether the default config is taken for missing or broken config files.
+    pub fn config_fallback(&self) -> bool {
+        self.config_fallback
+    }
     pub fn config_file(&self) -> &Option<PathBuf> {
         &self.config_file
     }
@@ -108,7 +166,8 @@ impl Args {
         &self.endings
     }
 
-    pub fn threads(&self) -> usize {
+    /// The number of threads given on the command line (0 means one per CPU).
+    pub fn threads(&self) -> Option<usize> {
         self.threads
     }
 
@@ -116,9 +175,24 @@ impl Args {
         self.line_length
     }
 
+    /// The tab width given on the command line (overrides the config file).
+    pub fn tab_width(&self) -> Option<usize> {
+        self.tab_width
+    }
+
     pub fn info_level(&self) -> InfoLevel {
         self.info_level
     }
+
+    /// The format of the final report.
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// The subcommand to run, if any.
+    pub fn command(&self) -> Option<&Command> {
+        self.command.as_ref()
+    }
 }
 
 /// `ArgMatches` wraps `clap::ArgMatches` and provides semantic meaning to
@@ -136,43 +210,71 @@ impl<'a> ArgMatches<'a> {
     /// Convert the result of parsing CLI arguments into enforcer's
     /// configuration.
     fn to_args(&self) -> Result<Args, num::ParseIntError> {
-        let path = self.path();
+        let paths = self.paths();
         let endings = self.endings();
         let config = self.config_path();
         let quiet = self.is_present("quiet");
-        let use_crlf = self.is_present("crlf");
+        let use_crlf = self.is_present("use_crlf");
         let args = Args {
-            path,
+            paths,
             endings,
             clean: self.is_present("clean"),
             config_file: config,
+            config_fallback: self.is_present("config-fallback"),
             line_length: self.usize_of("L")?,
+            tab_width: self.usize_of("tab_width")?,
             color: self.is_present("color"),
             quiet,
             use_crlf,
-            threads: self.threads()?,
+            line_ending: self.value_of("line-ending").and_then(|e| e.parse().ok()),
+            editorconfig: self.is_present("editorconfig"),
+            threads: self.usize_of("N")?,
             status: self.is_present("status"),
             tabs: self.is_present("tabs"),
+            staged: self.is_present("staged"),
+            files_from: self.value_of_os("files-from").map(PathBuf::from),
+            stdin: self.is_present("stdin"),
+            stdin_filename: self.value_of_os("stdin-filename").map(PathBuf::from),
+            use_ignore_files: !self.is_present("no-ignore-files"),
+            diff_base: self.value_of("diff-base").map(String::from),
+            clean_changed_only: self.is_present("clean-changed-only"),
             info_level: self.info_level(),
+            format: self.format(),
+            command: self.command(),
         };
         Ok(args)
     }
 
     /// Return all file endings that enforcer should search.
     fn endings(&self) -> Vec<String> {
-        let endings: Vec<String> = match self.values_of_lossy("endings") {
-            None => vec![],
-            Some(vals) => vals,
-        };
-        endings
+        self.values_of_lossy("endings").unwrap_or_default()
+    }
+
+    /// Return all paths that enforcer should search.
+    fn paths(&self) -> Vec<PathBuf> {
+        match self.values_of_os("path") {
+            None => vec![self.default_path()],
+            Some(vals) => vals.map(|val| Path::new(val).to_path_buf()).collect(),
+        }
     }
 
-    /// Return file path that enforcer should search.
-    fn path(&self) -> PathBuf {
-        match self.value_of_os("path") {
-            None => self.default_path(),
-            Some(val) => Path::new(val).to_path_buf(),
+    fn command(&self) -> Option<Command> {
+        let path = |m: &clap::ArgMatches| {
+            m.value_of_os("path")
+                .map_or_else(|| self.default_path(), PathBuf::from)
+        };
+        if let Some(init) = self.subcommand_matches("init") {
+            return Some(Command::Init {
+                path: path(init),
+                force: init.is_present("force"),
+            });
         }
+        let config = self.subcommand_matches("config")?;
+        let migrate = config.subcommand_matches("migrate")?;
+        Some(Command::Migrate {
+            path: path(migrate),
+            check: migrate.is_present("check"),
+        })
     }
 
     /// Return path to config file.
@@ -184,12 +286,17 @@ impl<'a> ArgMatches<'a> {
         }
     }
 
+    /// Return the requested report format (validated by clap).
+    fn format(&self) -> Format {
+        self.value_of("format")
+            .and_then(|f| f.parse().ok())
+            .unwrap_or(Format::Text)
+    }
+
     /// Return path to config file.
     fn config_path(&self) -> Option<PathBuf> {
-        match self.value_of_os("config-path") {
-            None => None,
-            Some(val) => Some(Path::new(val).to_path_buf()),
-        }
+        self.value_of_os("config-path")
+            .map(|val| Path::new(val).to_path_buf())
     }
 
     /// Return the default path that enforcer should search.
@@ -198,21 +305,12 @@ impl<'a> ArgMatches<'a> {
     }
 
     /// Returns the approximate number of threads that enforcer should use.
-    fn threads(&self) -> Result<usize, num::ParseIntError> {
-        let threads = self.usize_of("N")?.unwrap_or(0);
-        Ok(if threads == 0 {
-            cmp::min(12, num_cpus::get())
-        } else {
-            threads
-        })
-    }
-
     /// Safely reads an arg value with the given name, and if it's present,
     /// tries to parse it as a usize value.
     fn usize_of(&self, name: &str) -> Result<Option<usize>, num::ParseIntError> {
         match self.0.value_of_lossy(name) {
             None => Ok(None),
-            Some(v) => v.parse().map(Some).map_err(From::from),
+            Some(v) => v.parse().map(Some),
         }
     }
 }
@@ -1,17 +1,21 @@
-use ansi_term;
+use serde::{
+    de::{self, Deserialize, Deserializer},
+    Serialize, Serializer,
+};
 use std::{
     self,
     fs::{metadata, File},
     io,
     io::prelude::*,
-    path::Path,
+    ops::Range,
+    path::{Path, PathBuf},
 };
 use unic_char_range::CharRange;
 
 #[cfg(not(target_os = "windows"))]
 use term_painter::{Attr::*, Color, Painted, ToStyle};
 
-use crate::clean;
+use crate::{clean, git, syntax};
 use std::sync::mpsc::SyncSender;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -59,215 +63,737 @@ static UTF8_ALLOWED_RANGES: &[CharRange] = &[
     },
 ];
 
-pub const HAS_TABS: u8 = 1;
-pub const TRAILING_SPACES: u8 = 1 << 1;
-pub const HAS_ILLEGAL_CHARACTERS: u8 = 1 << 2;
-pub const LINE_TOO_LONG: u8 = 1 << 3;
-pub const HAS_WINDOWS_LINE_ENDINGS: u8 = 1 << 4;
+/// The rules enforcer checks a file against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Rule {
+    Tabs,
+    TrailingSpaces,
+    IllegalCharacters,
+    LineTooLong,
+    LineEndings,
+    MissingFinalNewline,
+    TrailingBlankLines,
+    ByteOrderMark,
+    MixedIndentation,
+    IndentationWidth,
+}
 
-fn check_content<'a>(
-    input: &'a str,
-    filename: &str,
-    info_level: InfoLevel,
+impl Rule {
+    pub const ALL: &'static [Rule] = &[
+        Rule::Tabs,
+        Rule::IllegalCharacters,
+        Rule::TrailingSpaces,
+        Rule::LineTooLong,
+        Rule::LineEndings,
+        Rule::MissingFinalNewline,
+        Rule::TrailingBlankLines,
+        Rule::ByteOrderMark,
+        Rule::MixedIndentation,
+        Rule::IndentationWidth,
+    ];
+
+    /// Stable identifier of the rule, used in all reports.
+    pub fn id(self) -> &'static str {
+        match self {
+            Rule::Tabs => "HAS_TABS",
+            Rule::TrailingSpaces => "TRAILING_SPACES",
+            Rule::IllegalCharacters => "HAS_ILLEGAL_CHARACTERS",
+            Rule::LineTooLong => "LINE_TOO_LONG",
+            // kept from when only CRLF was detected, reports and configs use it
+            Rule::LineEndings => "HAS_WINDOWS_LINE_ENDINGS",
+            Rule::MissingFinalNewline => "MISSING_FINAL_NEWLINE",
+            Rule::TrailingBlankLines => "EXCESS_TRAILING_BLANK_LINES",
+            Rule::ByteOrderMark => "HAS_BOM",
+            Rule::MixedIndentation => "MIXED_INDENTATION",
+            Rule::IndentationWidth => "INDENTATION_WIDTH",
+        }
+    }
+
+    /// Name of the rule in the summary of the text report.
+    pub fn label(self) -> &'static str {
+        match self {
+            Rule::Tabs => "TABS",
+            Rule::TrailingSpaces => "TRAILING SPACES",
+            Rule::IllegalCharacters => "ILLEGAL CHARS",
+            Rule::LineTooLong => "TOO LONG LINES",
+            Rule::LineEndings => "WRONG LINE ENDINGS",
+            Rule::MissingFinalNewline => "MISSING FINAL NEWLINE",
+            Rule::TrailingBlankLines => "TRAILING BLANK LINES",
+            Rule::ByteOrderMark => "BYTE ORDER MARK",
+            Rule::MixedIndentation => "MIXED INDENTATION",
+            Rule::IndentationWidth => "INDENTATION WIDTH",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Rule::Tabs => "tab characters are not allowed",
+            Rule::TrailingSpaces => "lines must not end with whitespace",
+            Rule::IllegalCharacters => "only ASCII and some selected UTF-8 characters are allowed",
+            Rule::LineTooLong => "lines must not exceed the maximum line length",
+            Rule::LineEndings => "lines must end with the line ending of the policy",
+            Rule::MissingFinalNewline => "the last line must end with a line ending",
+            Rule::TrailingBlankLines => "files must not end with blank lines",
+            Rule::ByteOrderMark => "files must start with a byte order mark only if required",
+            Rule::MixedIndentation => "lines must not mix tabs and spaces for indentation",
+            Rule::IndentationWidth => "indentation must be a multiple of the indent width",
+        }
+    }
+}
+
+impl std::str::FromStr for Rule {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Rule, String> {
+        Rule::ALL
+            .iter()
+            .find(|r| r.id() == s)
+            .copied()
+            .ok_or_else(|| {
+                let ids: Vec<&str> = Rule::ALL.iter().map(|r| r.id()).collect();
+                format!("unknown rule {:?}, expected one of {}", s, ids.join(", "))
+            })
+    }
+}
+
+// rules are written with their id in the config
+impl<'de> Deserialize<'de> for Rule {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Rule, D::Error> {
+        let id = String::deserialize(deserializer)?;
+        id.parse().map_err(de::Error::custom)
+    }
+}
+
+impl Serialize for Rule {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.id())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    #[allow(dead_code)]
+    Warning,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A single violation of a rule. `line` and `column` are 1-based, the column
+/// counts characters (not bytes).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub path: PathBuf,
+    pub line: usize,
+    pub column: usize,
+    pub rule: Rule,
+    pub message: String,
+    pub severity: Severity,
+    /// the offending line without surrounding whitespace
+    pub source: String,
+}
+
+impl Finding {
+    fn new(path: &Path, line: usize, column: usize, rule: Rule, message: String) -> Finding {
+        Finding {
+            path: path.to_path_buf(),
+            line,
+            column,
+            rule,
+            message,
+            severity: Severity::Error,
+            source: String::new(),
+        }
+    }
+}
+
+/// Restricts reported findings (and optionally cleaning) to some lines,
+/// e.g. the ones that changed compared to a git ref.
+#[derive(Debug, Clone, Copy)]
+pub struct LineFilter<'a> {
+    pub ranges: &'a [Range<usize>],
+    pub restrict_clean: bool,
+}
+
+impl LineFilter<'_> {
+    fn contains(&self, line: usize) -> bool {
+        git::is_changed(line, self.ranges)
+    }
+}
+
+pub fn has_rule(findings: &[Finding], rule: Rule) -> bool {
+    findings.iter().any(|f| f.rule == rule)
+}
+
+fn is_allowed(c: char) -> bool {
+    UTF8_ALLOWED_RANGES.iter().any(|range| range.contains(c))
+}
+
+// the first character (counted from 1) that ends past `max_len` columns with
+// the tabs expanded
+fn overlong_column(line: &str, max_len: usize, tab_width: usize) -> usize {
+    let mut width = 0;
+    for (i, c) in line.chars().enumerate() {
+        width = match c {
+            '\t' => width + tab_width - width % tab_width,
+            _ => width + 1,
+        };
+        if width > max_len {
+            return i + 1;
+        }
+    }
+    line.chars().count() + 1
+}
+
+fn check_content(
+    input: &str,
+    path: &Path,
     max_line_length: Option<usize>,
     s: clean::TabStrategy,
-    logger: SyncSender<Option<String>>,
-) -> io::Result<u8> {
-    let mut result = 0;
-    let mut i: u32 = 0;
-    for line in input.lines() {
-        i += 1;
+    tab_width: usize,
+    line_ending: clean::LineEnding,
+) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let name = |ending: clean::LineEnding| match ending {
+        clean::LineEnding::CRLF => "CRLF",
+        _ => "LF",
+    };
+    for (i, raw_line) in input.split_inclusive('\n').enumerate() {
+        let line_nr = i + 1;
+        let has_crlf = raw_line.ends_with("\r\n");
+        let line = raw_line.trim_end_matches('\n').trim_end_matches('\r');
+        let finding = |column: usize, rule: Rule, message: String| {
+            Finding::new(path, line_nr, column, rule, message)
+        };
 
         if let Some(max_len) = max_line_length {
-            if line.len() > max_len {
-                result |= LINE_TOO_LONG;
-                if info_level == InfoLevel::Verbose {
-                    let _ = logger.send(Some(format!(
-                        "{}, line {}: error: LINE_TOO_LONG\n",
-                        filename, i
-                    )));
-                }
+            let width = clean::expanded_width(line, tab_width);
+            if width > max_len {
+                findings.push(finding(
+                    overlong_column(line, max_len, tab_width),
+                    Rule::LineTooLong,
+                    format!("line is {} columns long (maximum is {})", width, max_len),
+                ));
             }
         }
-        if line.ends_with(' ') || line.ends_with('\t') {
-            result |= TRAILING_SPACES;
-            if info_level == InfoLevel::Verbose {
-                let _ = logger.send(Some(format!(
-                    "{}, line {}: error: TRAILING_SPACES\n",
-                    filename, i
-                )));
+        let trimmed = line.trim_end_matches([' ', '\t']);
+        if trimmed.len() < line.len() {
+            findings.push(finding(
+                trimmed.chars().count() + 1,
+                Rule::TrailingSpaces,
+                String::from("trailing whitespace"),
+            ));
+        }
+        if s == clean::TabStrategy::Untabify {
+            if let Some(pos) = line.chars().position(|c| c == '\t') {
+                findings.push(finding(pos + 1, Rule::Tabs, String::from("tab character")));
             }
         }
-        if s == clean::TabStrategy::Untabify && line.contains('\t') {
-            result |= HAS_TABS;
-            if info_level == InfoLevel::Verbose {
-                let _ = logger.send(Some(format!("{}, line {}: error: HAS_TABS\n", filename, i)));
+        if let Some((pos, c)) = line.chars().enumerate().find(|(_, c)| !is_allowed(*c)) {
+            findings.push(finding(
+                pos + 1,
+                Rule::IllegalCharacters,
+                format!("non ASCII character {:?} (U+{:04X})", c, c as u32),
+            ));
+        }
+        let body = raw_line
+            .strip_suffix("\r\n")
+            .or_else(|| raw_line.strip_suffix('\n'))
+            .unwrap_or(raw_line);
+        if let Some(pos) = body.chars().position(|c| c == '\r') {
+            findings.push(finding(
+                pos + 1,
+                Rule::LineEndings,
+                format!("line ending CR (expected {})", name(line_ending)),
+            ));
+        } else if raw_line.ends_with('\n') && has_crlf != (line_ending == clean::LineEnding::CRLF) {
+            let found = if has_crlf {
+                clean::LineEnding::CRLF
+            } else {
+                clean::LineEnding::LF
+            };
+            findings.push(finding(
+                line.chars().count() + 1,
+                Rule::LineEndings,
+                format!(
+                    "line ending {} (expected {})",
+                    name(found),
+                    name(line_ending)
+                ),
+            ));
+        }
+    }
+    findings.extend(check_end_of_file(input, path));
+    findings.sort_by_key(|f| (f.line, f.column));
+    findings
+}
+
+// a non-empty file ends with exactly one line ending, both problems are
+// reported at the last line
+fn check_end_of_file(input: &str, path: &Path) -> Vec<Finding> {
+    let lines: Vec<&str> = input.split_inclusive('\n').collect();
+    let last = match lines.last() {
+        Some(last) => last,
+        None => return Vec::new(),
+    };
+    let mut findings = Vec::new();
+    if !last.ends_with('\n') {
+        findings.push(Finding::new(
+            path,
+            lines.len(),
+            last.chars().count() + 1,
+            Rule::MissingFinalNewline,
+            String::from("no line ending at end of file"),
+        ));
+    }
+    let blank = lines
+        .iter()
+        .rev()
+        .take_while(|l| l.trim().is_empty())
+        .count();
+    if blank > 0 && blank < lines.len() {
+        findings.push(Finding::new(
+            path,
+            lines.len(),
+            1,
+            Rule::TrailingBlankLines,
+            format!("{} blank line(s) at end of file", blank),
+        ));
+    }
+    findings
+}
+
+// lines whose indentation mixes tabs and spaces (with smart tabs only spaces
+// before a tab count) and lines indented differently than most lines of the
+// file; an indentation of spaces narrower than one tab is only alignment
+fn check_indentation(
+    input: &str,
+    path: &Path,
+    s: clean::TabStrategy,
+    tab_width: usize,
+) -> Vec<Finding> {
+    let indents: Vec<Option<&str>> = input
+        .lines()
+        .map(|line| {
+            let code = line.trim_start_matches([' ', '\t']);
+            Some(&line[..line.len() - code.len()]).filter(|_| !code.trim().is_empty())
+        })
+        .collect();
+    let tabs = |indent: &str| indent.starts_with('\t');
+    let spaces = |indent: &str| !indent.contains('\t') && indent.len() >= tab_width;
+    let tab_lines = indents.iter().flatten().filter(|i| tabs(i)).count();
+    let space_lines = indents.iter().flatten().filter(|i| spaces(i)).count();
+    let mut findings = Vec::new();
+    for (i, indent) in indents.iter().enumerate() {
+        let indent = match indent {
+            Some(indent) => *indent,
+            None => continue,
+        };
+        let mixed = match s {
+            clean::TabStrategy::SmartTabs => {
+                indent.find(' ').filter(|pos| indent[*pos..].contains('\t'))
+            }
+            _ => indent.find(|c: char| !indent.starts_with(c)),
+        };
+        let (column, message) = if let Some(pos) = mixed {
+            let message = match s {
+                clean::TabStrategy::SmartTabs => "space before a tab in the indentation",
+                _ => "indentation mixes tabs and spaces",
+            };
+            (pos + 1, message)
+        } else if tabs(indent) && space_lines > tab_lines {
+            (1, "indented with tabs, most lines use spaces")
+        } else if spaces(indent) && tab_lines > space_lines {
+            (1, "indented with spaces, most lines use tabs")
+        } else {
+            continue;
+        };
+        findings.push(Finding::new(
+            path,
+            i + 1,
+            column,
+            Rule::MixedIndentation,
+            String::from(message),
+        ));
+    }
+    findings
+}
+
+// indented lines that do not start at a multiple of the indent width, lines
+// inside block comments and multi-line strings are left out
+fn check_indent_width(
+    input: &str,
+    path: &Path,
+    indent_width: usize,
+    tab_width: usize,
+) -> Vec<Finding> {
+    let continued = syntax::continued_lines(path, input);
+    input
+        .lines()
+        .zip(continued)
+        .enumerate()
+        .filter(|(_, (line, continued))| !continued && !line.trim().is_empty())
+        .filter_map(|(i, (line, _))| {
+            let indent = &line[..line.len() - line.trim_start_matches([' ', '\t']).len()];
+            let width = clean::to_spaces(indent.chars(), tab_width).len();
+            let excess = width % indent_width;
+            if excess == 0 {
+                return None;
+            }
+            Some(Finding::new(
+                path,
+                i + 1,
+                indent.chars().count() + 1,
+                Rule::IndentationWidth,
+                format!(
+                    "indentation of {} columns is not a multiple of {}",
+                    width, indent_width
+                ),
+            ))
+        })
+        .collect()
+}
+
+// report findings over the logger channel: every finding when verbose, one
+// line per violated rule otherwise
+fn log_findings(
+    filename: &str,
+    findings: &[Finding],
+    info_level: InfoLevel,
+    logger: &SyncSender<Option<String>>,
+) {
+    match info_level {
+        InfoLevel::Quiet => (),
+        InfoLevel::Verbose => {
+            for f in findings {
+                let _ = logger.send(Some(format!(
+                    "{}, line {}, column {}: {}: {}: {}\n",
+                    filename,
+                    f.line,
+                    f.column,
+                    f.severity,
+                    f.rule.id(),
+                    f.message
+                )));
             }
         }
-        if !line
-            .chars()
-            .all(|c| UTF8_ALLOWED_RANGES.iter().any(|range| range.contains(c)))
-        {
-            result |= HAS_ILLEGAL_CHARACTERS;
-            if info_level == InfoLevel::Verbose {
+        InfoLevel::Normal => {
+            for rule in Rule::ALL.iter().filter(|r| has_rule(findings, **r)) {
                 let _ = logger.send(Some(format!(
-                    "{}, line {}: error: non ASCII line\n",
-                    filename, i
+                    "{}, some lines with {}\n",
+                    filename,
+                    rule.id()
                 )));
             }
         }
     }
-    if input.contains("\r\n") {
-        result |= HAS_WINDOWS_LINE_ENDINGS;
-        if info_level == InfoLevel::Verbose {
-            let _ = logger.send(Some(format!(
-                "{}: error: HAS_WINDOWS_LINE_ENDINGS\n",
-                filename
-            )));
+}
+
+pub fn is_dir(path: &Path) -> bool {
+    if let Ok(result) = metadata(path) {
+        result.is_dir()
+    } else {
+        false
+    }
+}
+
+// find the lines that cannot be decoded as UTF-8
+fn find_non_utf8_lines(path: &Path, buf: &[u8]) -> Vec<Finding> {
+    buf.split(|b| *b == b'\n')
+        .enumerate()
+        .filter_map(|(i, line)| match std::str::from_utf8(line) {
+            Ok(_) => None,
+            Err(e) => {
+                let valid = std::str::from_utf8(&line[..e.valid_up_to()]).unwrap_or("");
+                Some(Finding::new(
+                    path,
+                    i + 1,
+                    valid.chars().count() + 1,
+                    Rule::IllegalCharacters,
+                    String::from("non UTF-8 character in line"),
+                ))
+            }
+        })
+        .collect()
+}
+
+// byte order marks of the encodings enforcer cannot check, UTF-32 LE
+// first as it starts like UTF-16 LE
+const FOREIGN_BOMS: &[(&[u8], &str)] = &[
+    (b"\xff\xfe\x00\x00", "UTF-32 LE"),
+    (b"\x00\x00\xfe\xff", "UTF-32 BE"),
+    (b"\xff\xfe", "UTF-16 LE"),
+    (b"\xfe\xff", "UTF-16 BE"),
+];
+
+fn foreign_bom(buf: &[u8]) -> Option<&'static str> {
+    FOREIGN_BOMS
+        .iter()
+        .find(|(bom, _)| buf.starts_with(bom))
+        .map(|(_, encoding)| *encoding)
+}
+
+// a byte order mark that is missing, forbidden or not UTF-8
+fn check_bom(path: &Path, buf: &[u8], bom: clean::Bom) -> Option<Finding> {
+    let finding = |message: String| Finding::new(path, 1, 1, Rule::ByteOrderMark, message);
+    if let Some(encoding) = foreign_bom(buf) {
+        return Some(finding(format!(
+            "{} byte order mark, the file is not UTF-8",
+            encoding
+        )));
+    }
+    let has_bom = buf.starts_with(clean::UTF8_BOM.as_bytes());
+    match bom {
+        clean::Bom::Forbid if has_bom => Some(finding(String::from("UTF-8 byte order mark"))),
+        clean::Bom::Require if !has_bom && !buf.is_empty() => {
+            Some(finding(String::from("missing UTF-8 byte order mark")))
         }
+        _ => None,
+    }
+}
+
+// any line that is indented with at least one space
+fn has_leading_spaces(input: &str) -> bool {
+    input.lines().any(|line| {
+        let indent = line.len() - line.trim_start_matches([' ', '\t']).len();
+        line[..indent].contains(' ')
+    })
+}
+
+/// Everything that decides how a single file is checked (and cleaned).
+#[derive(Debug, Clone)]
+pub struct CheckOptions {
+    pub clean: bool,
+    pub info_level: InfoLevel,
+    pub max_line_length: Option<usize>,
+    pub indent_width: Option<usize>,
+    pub tab_strategy: clean::TabStrategy,
+    pub tab_width: usize,
+    pub line_ending: clean::LineEnding,
+    pub bom: clean::Bom,
+    pub disabled_rules: Vec<Rule>,
+}
+
+impl CheckOptions {
+    /// Whether findings of a rule are reported (and cleaned).
+    pub fn is_enabled(&self, rule: Rule) -> bool {
+        !self.disabled_rules.contains(&rule)
     }
-    if info_level == InfoLevel::Normal {
-        if (result & LINE_TOO_LONG) > 0 {
+
+    /// Switch a rule on or off.
+    pub fn set_enabled(&mut self, rule: Rule, enabled: bool) {
+        self.disabled_rules.retain(|r| *r != rule);
+        if !enabled {
+            self.disabled_rules.push(rule);
+        }
+    }
+}
+
+/// Check the content of a file. When cleaning, the cleaned content is
+/// returned as well (unless the content could not be decoded).
+pub fn check_buffer(
+    path: &Path,
+    buf: &[u8],
+    opts: &CheckOptions,
+    line_filter: Option<LineFilter>,
+    logger: &SyncSender<Option<String>>,
+) -> (Vec<Finding>, Option<String>) {
+    let filename = path.to_str().expect("not available");
+    let info_level = opts.info_level;
+    let s = opts.tab_strategy;
+    let only_filtered = |findings: &[Finding]| -> Vec<Finding> {
+        findings
+            .iter()
+            .filter(|f| match line_filter {
+                Some(lf) => lf.contains(f.line),
+                None => true,
+            })
+            .cloned()
+            .collect()
+    };
+    let bom_finding = check_bom(path, buf, opts.bom).filter(|f| opts.is_enabled(f.rule));
+    let has_bom = buf.starts_with(clean::UTF8_BOM.as_bytes());
+    let content = if has_bom {
+        &buf[clean::UTF8_BOM.len()..]
+    } else {
+        buf
+    };
+    let buffer = match std::str::from_utf8(content) {
+        Err(_) => {
+            // only check content if we could read the file, a UTF-16/32
+            // byte order mark is reported instead of all its lines
+            let non_utf8: Vec<Finding> = match bom_finding {
+                Some(f) if foreign_bom(buf).is_some() => vec![f],
+                f => f
+                    .into_iter()
+                    .chain(find_non_utf8_lines(path, content))
+                    .collect(),
+            };
+            let findings: Vec<Finding> = only_filtered(&non_utf8)
+                .into_iter()
+                .filter(|f| opts.is_enabled(f.rule))
+                .collect();
+            log_findings(filename, &findings, info_level, logger);
+            return (findings, None);
+        }
+        Ok(buffer) => buffer,
+    };
+    // the line ending policy is resolved for every file
+    let line_ending = opts.line_ending.resolve(buffer);
+    // cleaning works on all findings, only the reported ones are filtered
+    let mut all_findings: Vec<Finding> = check_content(
+        buffer,
+        path,
+        opts.max_line_length,
+        s,
+        opts.tab_width,
+        line_ending,
+    )
+    .into_iter()
+    .chain(check_indentation(buffer, path, s, opts.tab_width))
+    .chain(
+        opts.indent_width
+            .map(|width| check_indent_width(buffer, path, width, opts.tab_width))
+            .unwrap_or_default(),
+    )
+    .filter(|f| opts.is_enabled(f.rule))
+    .chain(bom_finding)
+    .collect();
+    let lines: Vec<&str> = buffer.lines().collect();
+    for f in &mut all_findings {
+        if let Some(line) = lines.get(f.line - 1) {
+            f.source = line.trim().to_string();
+        }
+    }
+    let check = only_filtered(&all_findings);
+    log_findings(filename, &check, info_level, logger);
+    if !opts.clean {
+        return (check, None);
+    }
+    let no_trailing_ws = if has_rule(&all_findings, Rule::TrailingSpaces) {
+        if info_level == InfoLevel::Verbose {
             let _ = logger.send(Some(format!(
-                "{}, some lines with LINE_TOO_LONG\n",
-                filename
+                "TRAILING_SPACES:[{}] -> removing\n",
+                path.display()
             )));
         }
-        if (result & TRAILING_SPACES) > 0 {
+        clean::remove_trailing_whitespaces(buffer, &line_ending)
+    } else {
+        buffer.to_string()
+    };
+    let needs_tab_conversion = match s {
+        clean::TabStrategy::Untabify => has_rule(&all_findings, Rule::Tabs),
+        clean::TabStrategy::Tabify => has_leading_spaces(&no_trailing_ws),
+        clean::TabStrategy::SmartTabs => has_rule(&all_findings, Rule::MixedIndentation),
+    };
+    let space_tab_converted = if needs_tab_conversion {
+        if info_level == InfoLevel::Verbose {
+            let _ = logger.send(Some(match s {
+                clean::TabStrategy::Untabify => {
+                    format!("HAS_TABS:[{}] -> converting to spaces\n", path.display())
+                }
+                clean::TabStrategy::Tabify => format!(
+                    "LEADING_SPACES:[{}] -> converting to tabs\n",
+                    path.display()
+                ),
+                clean::TabStrategy::SmartTabs => format!(
+                    "MIXED_INDENTATION:[{}] -> converting indentation to tabs\n",
+                    path.display()
+                ),
+            }));
+        }
+        clean::space_tabs_conversion(no_trailing_ws, s, opts.tab_width, line_ending)
+    } else {
+        no_trailing_ws
+    };
+    let res_string = if has_rule(&all_findings, Rule::LineEndings) {
+        if info_level == InfoLevel::Verbose {
             let _ = logger.send(Some(format!(
-                "{}, some lines with TRAILING_SPACES\n",
-                filename
+                "HAS_WINDOWS_LINE_ENDINGS:[{}] -> converting to {}\n",
+                path.display(),
+                line_ending.as_str().escape_debug()
             )));
         }
-        if (result & HAS_TABS) > 0 {
-            let _ = logger.send(Some(format!("{}, some lines with HAS_TABS\n", filename)));
-        }
-        if (result & HAS_WINDOWS_LINE_ENDINGS) > 0 {
+        clean::normalize_line_endings(space_tab_converted, line_ending)
+    } else {
+        space_tab_converted
+    };
+    let res_string = if has_rule(&all_findings, Rule::TrailingBlankLines) {
+        if info_level == InfoLevel::Verbose {
             let _ = logger.send(Some(format!(
-                "{}, some lines with HAS_WINDOWS_LINE_ENDINGS\n",
-                filename
+                "EXCESS_TRAILING_BLANK_LINES:[{}] -> removing\n",
+                path.display()
             )));
         }
-        if (result & HAS_ILLEGAL_CHARACTERS) > 0 {
+        clean::remove_trailing_blank_lines(res_string)
+    } else {
+        res_string
+    };
+    let res_string = if has_rule(&all_findings, Rule::MissingFinalNewline) {
+        if info_level == InfoLevel::Verbose {
             let _ = logger.send(Some(format!(
-                "{}, some lines with non ASCII line\n",
-                filename
+                "MISSING_FINAL_NEWLINE:[{}] -> adding line ending\n",
+                path.display()
             )));
         }
-    }
-    Ok(result)
-}
-
-pub fn is_dir(path: &Path) -> bool {
-    if let Ok(result) = metadata(&path) {
-        result.is_dir()
+        clean::add_final_line_ending(res_string, &line_ending)
     } else {
-        false
-    }
-}
-
-fn report_offending_line(path: &Path, logger: SyncSender<Option<String>>) -> std::io::Result<()> {
-    use std::io::BufReader;
-    let mut i: u32 = 1;
-    let f = File::open(path)?;
-    let file = BufReader::new(f);
-    for line in file.lines() {
-        match line.ok() {
-            Some(_) => i += 1,
-            None => {
-                let _ = logger.send(Some(format!(
-                    "{}, line {}: error: non UTF-8 character in line\n",
-                    path.display(),
-                    i
-                )));
-            }
+        res_string
+    };
+    let res_string = match line_filter {
+        Some(lf) if lf.restrict_clean => {
+            clean::merge_lines(buffer, &res_string, |line| lf.contains(line))
         }
+        _ => res_string,
+    };
+    // the byte order mark is kept unless it is wrong
+    let with_bom = has_bom != has_rule(&all_findings, Rule::ByteOrderMark);
+    if with_bom != has_bom && info_level == InfoLevel::Verbose {
+        let action = if with_bom { "adding" } else { "removing" };
+        let _ = logger.send(Some(format!(
+            "HAS_BOM:[{}] -> {}\n",
+            path.display(),
+            action
+        )));
     }
-    Ok(())
+    let res_string = if with_bom {
+        String::from(clean::UTF8_BOM) + &res_string
+    } else {
+        res_string
+    };
+    (check, Some(res_string))
 }
 
-#[allow(clippy::too_many_arguments)]
+/// Check a file and write the cleaned content back when cleaning.
 pub fn check_path(
     path: &Path,
     buf: &[u8],
-    clean: bool,
-    info_level: InfoLevel,
-    max_line_length: Option<usize>,
-    s: clean::TabStrategy,
-    ending: clean::LineEnding,
+    opts: &CheckOptions,
+    line_filter: Option<LineFilter>,
     logger: SyncSender<Option<String>>,
-) -> io::Result<u8> {
-    let mut check = 0;
-    match std::str::from_utf8(buf) {
-        Err(_) => {
-            check |= HAS_ILLEGAL_CHARACTERS;
-            if info_level == InfoLevel::Normal {
-                let _ = report_offending_line(path, logger);
-            }
-            return Ok(check);
-        }
-        Ok(buffer) => {
-            if check == 0 {
-                check = check_content(
-                    &buffer,
-                    path.to_str().expect("not available"),
-                    info_level,
-                    max_line_length,
-                    s,
-                    logger.clone(),
-                )?;
-            }
-            let no_trailing_ws = if (check & TRAILING_SPACES) > 0 && clean {
-                if info_level == InfoLevel::Verbose {
-                    let _ = logger.send(Some(format!(
-                        "TRAILING_SPACES:[{}] -> removing\n",
-                        path.display()
-                    )));
-                }
-                clean::remove_trailing_whitespaces(buffer, &ending)
-            } else {
-                buffer.to_string()
-            };
-            let space_tab_converted = if (check & HAS_TABS) > 0 && clean {
-                if info_level == InfoLevel::Verbose {
-                    let _ = logger.send(Some(format!(
-                        "HAS_TABS:[{}] -> converting to spaces\n",
-                        path.display()
-                    )));
-                }
-                clean::space_tabs_conversion(no_trailing_ws, clean::TabStrategy::Untabify, ending)
-            } else {
-                no_trailing_ws
-            };
-            let res_string = if (check & HAS_WINDOWS_LINE_ENDINGS) > 0 && clean {
-                if info_level == InfoLevel::Verbose {
-                    let _ = logger.send(Some(format!(
-                        "HAS_WINDOWS_LINE_ENDINGS:[{}] -> converting CRLF to LF\n",
-                        path.display()
-                    )));
-                }
-                clean::replace_win_line_endings(space_tab_converted)
-            } else {
-                space_tab_converted
-            };
-            if clean {
-                let mut file = File::create(path)?;
-                file.write_all(res_string.as_bytes())?;
-            }
-        }
-    };
-    // only check content if we could read the file
-    Ok(check)
+) -> io::Result<Vec<Finding>> {
+    let (findings, cleaned) = check_buffer(path, buf, opts, line_filter, &logger);
+    if let Some(content) = cleaned {
+        let mut file = File::create(path)?;
+        file.write_all(content.as_bytes())?;
+    }
+    Ok(findings)
 }
 
 #[allow(dead_code)]
 #[cfg(not(target_os = "windows"))]
-pub fn red(s: &str) -> ansi_term::ANSIString {
+pub fn red(s: &str) -> ansi_term::ANSIString<'_> {
     ansi_term::Colour::Red.bold().paint(s)
 }
 #[allow(dead_code)]
 #[cfg(not(target_os = "windows"))]
-pub fn yellow(s: &str) -> ansi_term::ANSIString {
+pub fn yellow(s: &str) -> ansi_term::ANSIString<'_> {
     ansi_term::Colour::Yellow.bold().paint(s)
 }
 #[cfg(not(target_os = "windows"))]
@@ -279,55 +805,55 @@ pub fn bold(s: &str) -> Painted<&str> {
     Bold.paint(s)
 }
 #[cfg(target_os = "windows")]
-pub fn green(s: &str) -> ansi_term::ANSIString {
+pub fn green(s: &str) -> ansi_term::ANSIString<'_> {
     ansi_term::Style::new().paint(s)
 }
 #[cfg(target_os = "windows")]
-pub fn bold(s: &str) -> ansi_term::ANSIString {
+pub fn bold(s: &str) -> ansi_term::ANSIString<'_> {
     ansi_term::Style::new().paint(s)
 }
 
 #[cfg(test)]
 mod tests {
     use super::{
-        check_content, InfoLevel, HAS_ILLEGAL_CHARACTERS, HAS_TABS, HAS_WINDOWS_LINE_ENDINGS,
-        LINE_TOO_LONG, TRAILING_SPACES,
+        check_buffer, check_content, check_indent_width, check_indentation, find_non_utf8_lines,
+        has_rule, CheckOptions, Finding, InfoLevel, LineFilter, Rule,
     };
-    use crate::clean::TabStrategy::{Tabify, Untabify};
-    use std::sync::mpsc::sync_channel;
+    use crate::clean::{
+        Bom, LineEnding,
+        TabStrategy::{SmartTabs, Tabify, Untabify},
+    };
+    use std::{path::Path, sync::mpsc::sync_channel};
+
+    fn check(
+        content: &str,
+        max_line_length: Option<usize>,
+        s: super::clean::TabStrategy,
+    ) -> Vec<Finding> {
+        check_content(
+            content,
+            Path::new("foo.h"),
+            max_line_length,
+            s,
+            4,
+            LineEnding::LF,
+        )
+    }
 
     #[test]
     fn test_check_good_content() {
-        let (logging_tx, _) = sync_channel::<Option<String>>(0);
         let content = " 1\n";
-        let res = check_content(
-            content,
-            "foo.h",
-            InfoLevel::Quiet,
-            None,
-            Untabify,
-            logging_tx,
-        );
-        assert!(res.is_ok());
-        let check = res.unwrap();
-        assert!((check & TRAILING_SPACES) == 0);
-        assert!((check & HAS_TABS) == 0);
-        assert!((check & HAS_ILLEGAL_CHARACTERS) == 0);
+        let check = check(content, None, Untabify);
+        assert!(check.is_empty());
     }
     #[test]
     fn test_check_good_content_with_tabs() {
-        let (logging_tx, _) = sync_channel::<Option<String>>(0);
         let content = "\t1\n";
-        let res = check_content(content, "foo.h", InfoLevel::Quiet, None, Tabify, logging_tx);
-        assert!(res.is_ok());
-        let check = res.unwrap();
-        assert!((check & TRAILING_SPACES) == 0);
-        assert!((check & HAS_TABS) == 0);
-        assert!((check & HAS_ILLEGAL_CHARACTERS) == 0);
+        let check = check(content, None, Tabify);
+        assert!(check.is_empty());
     }
     #[test]
     fn test_check_good_content_with_box_drawing() {
-        let (logging_tx, _) = sync_channel::<Option<String>>(0);
         let content = r#"
                      ▲
                      │
@@ -339,140 +865,429 @@ mod tests {
             └─────────────────┘
             "#;
 
-        let res = check_content(content, "foo.h", InfoLevel::Quiet, None, Tabify, logging_tx);
-        assert!(res.is_ok());
-        let check = res.unwrap();
+        let check = check(content, None, Tabify);
 
         // There are trailing spaces in the string literal, but otherwise it's good
-        assert!((check & HAS_TABS) == 0);
-        assert!((check & HAS_ILLEGAL_CHARACTERS) == 0);
+        assert!(!has_rule(&check, Rule::Tabs));
+        assert!(!has_rule(&check, Rule::IllegalCharacters));
     }
     #[test]
     fn test_check_bad_content_with_illegal_characters() {
-        let (logging_tx, _) = sync_channel::<Option<String>>(0);
         let content = r#"
             Бл҃же́нъ мꙋ́жъ, и҆́же не и҆́де на совѣ́тъ нечести́выхъ,
             и҆ на пꙋтѝ грѣ́шныхъ не ста̀, и҆ на сѣда́лищи гꙋби́телей
             не сѣ́де: но въ зако́нѣ гдⷭ҇ни во́лѧ є҆гѡ̀, и҆ въ зако́нѣ
             є҆гѡ̀ поꙋчи́тсѧ де́нь и҆ но́щь."#;
 
-        let res = check_content(content, "foo.h", InfoLevel::Quiet, None, Tabify, logging_tx);
-        assert!(res.is_ok());
-        let check = res.unwrap();
+        let check = check(content, None, Tabify);
 
-        assert!((check & TRAILING_SPACES) == 0);
-        assert!((check & HAS_TABS) == 0);
-        assert!((check & HAS_ILLEGAL_CHARACTERS) != 0);
+        assert!(!has_rule(&check, Rule::TrailingSpaces));
+        assert!(!has_rule(&check, Rule::Tabs));
+        let illegal: Vec<&Finding> = check
+            .iter()
+            .filter(|f| f.rule == Rule::IllegalCharacters)
+            .collect();
+        assert_eq!(illegal.len(), 4);
+        assert_eq!((illegal[0].line, illegal[0].column), (2, 13));
     }
     #[test]
     fn test_check_bad_content_with_tabs() {
-        let (logging_tx, _) = sync_channel::<Option<String>>(0);
         let content = "\t1\n";
-        let res = check_content(
-            content,
-            "foo.h",
-            InfoLevel::Quiet,
-            None,
-            Untabify,
-            logging_tx,
-        );
-        assert!(res.is_ok());
-        let check = res.unwrap();
-        assert!((check & TRAILING_SPACES) == 0);
-        assert!((check & HAS_TABS) == 1);
-        assert!((check & HAS_ILLEGAL_CHARACTERS) == 0);
+        let check = check(content, None, Untabify);
+        assert_eq!(check.len(), 1);
+        assert_eq!(check[0].rule, Rule::Tabs);
+        assert_eq!((check[0].line, check[0].column), (1, 1));
     }
     #[test]
     fn test_check_bad_content_with_win_line_endings() {
-        let (logging_tx, _) = sync_channel::<Option<String>>(0);
         let content = "1\r\n2\r\n";
-        let res = check_content(
-            content,
-            "foo.h",
-            InfoLevel::Quiet,
-            None,
-            Untabify,
-            logging_tx,
+        let check = check(content, None, Untabify);
+        assert!(!has_rule(&check, Rule::TrailingSpaces));
+        assert!(!has_rule(&check, Rule::Tabs));
+        assert!(!has_rule(&check, Rule::IllegalCharacters));
+        let lines: Vec<(usize, usize)> = check
+            .iter()
+            .filter(|f| f.rule == Rule::LineEndings)
+            .map(|f| (f.line, f.column))
+            .collect();
+        assert_eq!(lines, vec![(1, 2), (2, 2)]);
+    }
+    #[test]
+    fn test_line_ending_policy() {
+        let found = |content: &str, ending: LineEnding| -> Vec<(usize, usize, String)> {
+            check_content(content, Path::new("foo.h"), None, Untabify, 4, ending)
+                .into_iter()
+                .filter(|f| f.rule == Rule::LineEndings)
+                .map(|f| (f.line, f.column, f.message))
+                .collect()
+        };
+        let content = "a\r\nb\nc\rd\r\ne\r";
+        assert_eq!(
+            found(content, LineEnding::CRLF),
+            vec![
+                (2, 2, String::from("line ending LF (expected CRLF)")),
+                (3, 2, String::from("line ending CR (expected CRLF)")),
+                (4, 2, String::from("line ending CR (expected CRLF)")),
+            ]
         );
-        assert!(res.is_ok());
-        let check = res.unwrap();
-        assert!((check & TRAILING_SPACES) == 0);
-        assert!((check & HAS_TABS) == 0);
-        assert!((check & HAS_WINDOWS_LINE_ENDINGS) > 0);
-        assert!((check & HAS_ILLEGAL_CHARACTERS) == 0);
+        let lines: Vec<usize> = found(content, LineEnding::LF).iter().map(|f| f.0).collect();
+        assert_eq!(lines, vec![1, 3, 4]);
+        assert_eq!(Rule::LineEndings.id(), "HAS_WINDOWS_LINE_ENDINGS");
+        assert_eq!("HAS_WINDOWS_LINE_ENDINGS".parse(), Ok(Rule::LineEndings));
     }
     #[test]
     fn test_check_content_trailing_ws() {
-        let (logging_tx, _) = sync_channel::<Option<String>>(0);
         let content = "1 \n";
-        let res = check_content(
-            content,
-            "foo.h",
-            InfoLevel::Quiet,
-            None,
-            Untabify,
-            logging_tx,
-        );
-        assert!(res.is_ok());
-        let check = res.unwrap();
-        assert!((check & TRAILING_SPACES) > 0);
-        assert!((check & HAS_TABS) == 0);
-        assert!((check & HAS_ILLEGAL_CHARACTERS) == 0);
+        let check = check(content, None, Untabify);
+        assert_eq!(check.len(), 1);
+        assert_eq!(check[0].rule, Rule::TrailingSpaces);
+        assert_eq!((check[0].line, check[0].column), (1, 2));
     }
     #[test]
     fn test_check_content_trailing_tabs() {
-        let (logging_tx, _) = sync_channel::<Option<String>>(0);
         let content = "1\t\n";
-        let res = check_content(
-            content,
-            "foo.h",
-            InfoLevel::Quiet,
-            None,
-            Untabify,
-            logging_tx,
-        );
-        assert!(res.is_ok());
-        let check = res.unwrap();
-        assert!((check & TRAILING_SPACES) > 0);
-        assert!((check & HAS_TABS) > 0);
-        assert!((check & HAS_ILLEGAL_CHARACTERS) == 0);
+        let check = check(content, None, Untabify);
+        assert!(has_rule(&check, Rule::TrailingSpaces));
+        assert!(has_rule(&check, Rule::Tabs));
+        assert!(!has_rule(&check, Rule::IllegalCharacters));
     }
     #[test]
     fn test_line_too_long() {
-        let (logging_tx, _) = sync_channel::<Option<String>>(0);
         let content = "1234567890\n";
-        let res = check_content(
+        let check = check(content, Some(5), Tabify);
+        assert_eq!(check.len(), 1);
+        assert_eq!(check[0].rule, Rule::LineTooLong);
+        assert_eq!((check[0].line, check[0].column), (1, 6));
+    }
+    #[test]
+    fn test_line_too_long_with_expanded_tabs() {
+        let content = "\t\t12\n";
+        let check4 = check_content(
+            content,
+            Path::new("foo.h"),
+            Some(10),
+            Tabify,
+            4,
+            LineEnding::LF,
+        );
+        assert!(!has_rule(&check4, Rule::LineTooLong));
+        let check8 = check_content(
             content,
-            "foo.h",
-            InfoLevel::Quiet,
-            Some(5),
+            Path::new("foo.h"),
+            Some(10),
             Tabify,
-            logging_tx,
+            8,
+            LineEnding::LF,
         );
-        assert!(res.is_ok());
-        let check = res.unwrap();
-        assert!((check & TRAILING_SPACES) == 0);
-        assert!((check & HAS_TABS) == 0);
-        assert!((check & HAS_ILLEGAL_CHARACTERS) == 0);
-        assert!((check & LINE_TOO_LONG) > 0);
+        assert!(has_rule(&check8, Rule::LineTooLong));
+        assert_eq!(check8[0].column, 2);
     }
     #[test]
-    fn test_line_not_too_long() {
-        let (logging_tx, _) = sync_channel::<Option<String>>(0);
-        let content = "1234567890\n";
-        let res = check_content(
+    fn test_line_too_long_column_after_tab() {
+        let content = "\t123456789\n";
+        let check = check_content(
             content,
-            "foo.h",
-            InfoLevel::Quiet,
+            Path::new("foo.h"),
             Some(10),
             Tabify,
-            logging_tx,
+            4,
+            LineEnding::LF,
+        );
+        assert_eq!(check.len(), 1);
+        assert_eq!(check[0].rule, Rule::LineTooLong);
+        assert_eq!((check[0].line, check[0].column), (1, 8));
+    }
+    #[test]
+    fn test_line_not_too_long() {
+        let content = "1234567890\n";
+        let check = check(content, Some(10), Tabify);
+        assert!(check.is_empty());
+    }
+    #[test]
+    fn test_findings_on_several_lines() {
+        let content = "ok\nbad \nok\n\tbad\n";
+        let check = check(content, None, Untabify);
+        let found: Vec<(usize, Rule)> = check.iter().map(|f| (f.line, f.rule)).collect();
+        assert_eq!(found, vec![(2, Rule::TrailingSpaces), (4, Rule::Tabs)]);
+        assert!(check.iter().all(|f| f.path == Path::new("foo.h")));
+    }
+    #[test]
+    fn test_end_of_file() {
+        let found = |content: &str| -> Vec<(usize, usize, Rule)> {
+            check(content, None, Untabify)
+                .iter()
+                .map(|f| (f.line, f.column, f.rule))
+                .collect()
+        };
+        assert!(found("").is_empty());
+        assert!(found("\n").is_empty());
+        assert!(found("a\n").is_empty());
+        assert_eq!(found("a\nbc"), vec![(2, 3, Rule::MissingFinalNewline)]);
+        assert_eq!(found("a\n\n\n"), vec![(3, 1, Rule::TrailingBlankLines)]);
+        assert_eq!(
+            found("a\n\n\t"),
+            vec![
+                (3, 1, Rule::TrailingSpaces),
+                (3, 1, Rule::Tabs),
+                (3, 1, Rule::TrailingBlankLines),
+                (3, 2, Rule::MissingFinalNewline),
+            ]
+        );
+    }
+    #[test]
+    fn test_non_utf8_lines() {
+        let content = b"fine\nab\xffc\nfine\n";
+        let check = find_non_utf8_lines(Path::new("foo.h"), content);
+        assert_eq!(check.len(), 1);
+        assert_eq!(check[0].rule, Rule::IllegalCharacters);
+        assert_eq!((check[0].line, check[0].column), (2, 3));
+    }
+
+    #[test]
+    fn test_check_buffer() {
+        let (tx, _rx) = sync_channel(16);
+        let opts = CheckOptions {
+            clean: true,
+            info_level: InfoLevel::Quiet,
+            max_line_length: None,
+            indent_width: None,
+            tab_strategy: Untabify,
+            tab_width: 2,
+            line_ending: LineEnding::LF,
+            bom: Bom::Forbid,
+            disabled_rules: vec![],
+        };
+        let (findings, cleaned) = check_buffer(Path::new("foo.cpp"), b"\tx \r\n", &opts, None, &tx);
+        assert!(has_rule(&findings, Rule::Tabs));
+        assert!(has_rule(&findings, Rule::TrailingSpaces));
+        assert_eq!(findings[0].path, Path::new("foo.cpp"));
+        assert_eq!(findings[0].source, "x");
+        assert_eq!(cleaned.as_deref(), Some("  x\n"));
+
+        let opts = CheckOptions {
+            clean: false,
+            ..opts
+        };
+        let (findings, cleaned) = check_buffer(Path::new("foo.cpp"), b"x\n", &opts, None, &tx);
+        assert!(findings.is_empty());
+        assert!(cleaned.is_none());
+    }
+
+    #[test]
+    fn test_clean_changed_lines_with_lone_cr() {
+        let (tx, _rx) = sync_channel(16);
+        let opts = CheckOptions {
+            clean: true,
+            info_level: InfoLevel::Quiet,
+            max_line_length: None,
+            indent_width: None,
+            tab_strategy: Untabify,
+            tab_width: 4,
+            line_ending: LineEnding::LF,
+            bom: Bom::Forbid,
+            disabled_rules: vec![],
+        };
+        // only the second line changed, the lone CR of the first one stays
+        let changed = 2..3;
+        let filter = LineFilter {
+            ranges: std::slice::from_ref(&changed),
+            restrict_clean: true,
+        };
+        let (_, cleaned) =
+            check_buffer(Path::new("foo.c"), b"a\rb\nc \n", &opts, Some(filter), &tx);
+        assert_eq!(cleaned.as_deref(), Some("a\rb\nc\n"));
+    }
+
+    #[test]
+    fn test_clean_end_of_file() {
+        let (tx, _rx) = sync_channel(16);
+        let mut opts = CheckOptions {
+            clean: true,
+            info_level: InfoLevel::Quiet,
+            max_line_length: None,
+            indent_width: None,
+            tab_strategy: Untabify,
+            tab_width: 4,
+            line_ending: LineEnding::CRLF,
+            bom: Bom::Forbid,
+            disabled_rules: vec![],
+        };
+        let clean = |content: &[u8], opts: &CheckOptions| {
+            check_buffer(Path::new("foo.c"), content, opts, None, &tx).1
+        };
+        assert_eq!(clean(b"x\r\ny", &opts).as_deref(), Some("x\r\ny\r\n"));
+        assert_eq!(clean(b"x\r\n\r\n  \r\n", &opts).as_deref(), Some("x\r\n"));
+        assert_eq!(clean(b"x\n \n ", &opts).as_deref(), Some("x\r\n"));
+
+        opts.line_ending = LineEnding::Consistent;
+        assert_eq!(
+            clean(b"a\r\nb\nc\r\n", &opts).as_deref(),
+            Some("a\r\nb\r\nc\r\n")
+        );
+        assert_eq!(clean(b"a\rb\nc\n", &opts).as_deref(), Some("a\nb\nc\n"));
+
+        opts.line_ending = LineEnding::CRLF;
+        opts.set_enabled(Rule::TrailingBlankLines, false);
+        assert_eq!(clean(b"x\n\ny", &opts).as_deref(), Some("x\r\n\r\ny\r\n"));
+        assert_eq!(clean(b"x\r\n\r\n", &opts).as_deref(), Some("x\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_byte_order_mark() {
+        let (tx, _rx) = sync_channel(16);
+        let mut opts = CheckOptions {
+            clean: true,
+            info_level: InfoLevel::Quiet,
+            max_line_length: None,
+            indent_width: None,
+            tab_strategy: Untabify,
+            tab_width: 4,
+            line_ending: LineEnding::LF,
+            bom: Bom::Forbid,
+            disabled_rules: vec![],
+        };
+        let run = |content: &[u8], opts: &CheckOptions| {
+            let (findings, cleaned) = check_buffer(Path::new("foo.c"), content, opts, None, &tx);
+            let found: Vec<(usize, usize, Rule, String)> = findings
+                .into_iter()
+                .map(|f| (f.line, f.column, f.rule, f.message))
+                .collect();
+            (found, cleaned)
+        };
+        let (found, cleaned) = run(b"\xef\xbb\xbf\tx\n", &opts);
+        assert_eq!(
+            found,
+            vec![
+                (1, 1, Rule::Tabs, String::from("tab character")),
+                (
+                    1,
+                    1,
+                    Rule::ByteOrderMark,
+                    String::from("UTF-8 byte order mark")
+                ),
+            ]
+        );
+        assert_eq!(cleaned.as_deref(), Some("    x\n"));
+
+        let (found, cleaned) = run(b"\xff\xfex\x00\n\x00", &opts);
+        assert_eq!(
+            found,
+            vec![(
+                1,
+                1,
+                Rule::ByteOrderMark,
+                String::from("UTF-16 LE byte order mark, the file is not UTF-8")
+            )]
+        );
+        assert!(cleaned.is_none());
+
+        opts.bom = Bom::Require;
+        let (found, cleaned) = run(b"x\n", &opts);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].3, "missing UTF-8 byte order mark");
+        assert_eq!(cleaned.as_deref(), Some("\u{feff}x\n"));
+        let (found, cleaned) = run("\u{feff}x \n".as_bytes(), &opts);
+        assert_eq!(found[0].2, Rule::TrailingSpaces);
+        assert_eq!(cleaned.as_deref(), Some("\u{feff}x\n"));
+        assert!(run(b"", &opts).0.is_empty());
+
+        opts.set_enabled(Rule::ByteOrderMark, false);
+        assert!(run(b"x\n", &opts).0.is_empty());
+    }
+
+    #[test]
+    fn test_mixed_indentation() {
+        let found = |content: &str, s| -> Vec<(usize, usize, String)> {
+            check_indentation(content, Path::new("foo.c"), s, 4)
+                .into_iter()
+                .map(|f| (f.line, f.column, f.message))
+                .collect()
+        };
+        let content = "a\n\tb\n\t  c\n \td\n/*\n * e\n */\n\t\tf\n        g\n";
+        assert_eq!(
+            found(content, Tabify),
+            vec![
+                (3, 2, String::from("indentation mixes tabs and spaces")),
+                (4, 2, String::from("indentation mixes tabs and spaces")),
+                (
+                    9,
+                    1,
+                    String::from("indented with spaces, most lines use tabs")
+                ),
+            ]
+        );
+        assert_eq!(
+            found(content, SmartTabs),
+            vec![
+                (4, 1, String::from("space before a tab in the indentation")),
+                (
+                    9,
+                    1,
+                    String::from("indented with spaces, most lines use tabs")
+                ),
+            ]
+        );
+        let found_lines = |content: &str| -> Vec<usize> {
+            found(content, Untabify).iter().map(|f| f.0).collect()
+        };
+        assert_eq!(found_lines("    a\n    b\n\tc\n  \t\n"), vec![3]);
+        assert!(found_lines("\ta\n    b\n").is_empty());
+    }
+
+    #[test]
+    fn test_clean_smart_tabs() {
+        let (tx, _rx) = sync_channel(16);
+        let mut opts = CheckOptions {
+            clean: true,
+            info_level: InfoLevel::Quiet,
+            max_line_length: None,
+            indent_width: None,
+            tab_strategy: SmartTabs,
+            tab_width: 4,
+            line_ending: LineEnding::LF,
+            bom: Bom::Forbid,
+            disabled_rules: vec![Rule::MixedIndentation],
+        };
+        let content = b"\tif (a &&\n\t    b)\n  \tc;\n";
+        let (findings, cleaned) = check_buffer(Path::new("foo.c"), content, &opts, None, &tx);
+        assert!(findings.is_empty());
+        assert_eq!(cleaned.as_deref(), Some("\tif (a &&\n\t    b)\n  \tc;\n"));
+
+        opts.set_enabled(Rule::MixedIndentation, true);
+        let (findings, cleaned) = check_buffer(Path::new("foo.c"), content, &opts, None, &tx);
+        assert_eq!(findings.len(), 1);
+        assert_eq!((findings[0].line, findings[0].column), (3, 1));
+        assert_eq!(cleaned.as_deref(), Some("\tif (a &&\n\t    b)\n\tc;\n"));
+    }
+
+    #[test]
+    fn test_indent_width() {
+        let content = "int f() {\n    a();\n      b();\n\tc();\n\t  d();\n   \n/*\n * x\n */\n}\n";
+        let found = |name: &str, tab_width: usize| -> Vec<(usize, usize, String)> {
+            check_indent_width(content, Path::new(name), 4, tab_width)
+                .into_iter()
+                .map(|f| (f.line, f.column, f.message))
+                .collect()
+        };
+        assert_eq!(
+            found("foo.cpp", 4),
+            vec![
+                (
+                    3,
+                    7,
+                    String::from("indentation of 6 columns is not a multiple of 4")
+                ),
+                (
+                    5,
+                    4,
+                    String::from("indentation of 6 columns is not a multiple of 4")
+                ),
+            ]
         );
-        assert!(res.is_ok());
-        let check = res.unwrap();
-        assert!((check & TRAILING_SPACES) == 0);
-        assert!((check & HAS_TABS) == 0);
-        assert!((check & HAS_ILLEGAL_CHARACTERS) == 0);
-        assert!((check & LINE_TOO_LONG) == 0);
+        let lines = |found: Vec<(usize, usize, String)>| -> Vec<usize> {
+            found.iter().map(|f| f.0).collect()
+        };
+        assert_eq!(lines(found("foo.cpp", 2)), vec![3, 4]);
+        assert_eq!(lines(found("foo.txt", 4)), vec![3, 5, 8, 9]);
     }
 }
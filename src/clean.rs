@@ -1,19 +1,83 @@
-use std::str::Chars;
+use serde_derive::{Deserialize, Serialize};
+use std::str::{Chars, FromStr};
 
-#[derive(Clone, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum TabStrategy {
     Untabify,
     Tabify,
+    /// tabs for indentation, spaces for alignment
+    SmartTabs,
 }
-#[derive(Clone, PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum LineEnding {
     LF,
     CRLF,
+    /// the line ending of the platform enforcer runs on
+    Native,
+    /// the line ending most lines of a file already use
+    Consistent,
 }
 
-fn to_spaces(line: Chars, width: u8) -> String {
+/// Whether UTF-8 files start with a byte order mark.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Bom {
+    Forbid,
+    Require,
+}
+
+/// The UTF-8 byte order mark.
+pub const UTF8_BOM: &str = "\u{feff}";
+
+impl LineEnding {
+    pub const NAMES: &'static [&'static str] = &["lf", "crlf", "native", "consistent"];
+
+    /// The line ending (LF or CRLF) the content has to use. For `Consistent`
+    /// it is the one most lines end with, LF when there is no majority.
+    pub fn resolve(self, content: &str) -> LineEnding {
+        match self {
+            LineEnding::Native if cfg!(windows) => LineEnding::CRLF,
+            LineEnding::Native => LineEnding::LF,
+            LineEnding::Consistent => {
+                let crlf = content.matches("\r\n").count();
+                if crlf * 2 > content.matches('\n').count() {
+                    LineEnding::CRLF
+                } else {
+                    LineEnding::LF
+                }
+            }
+            ending => ending,
+        }
+    }
+
+    /// The characters that end a line (policies are resolved without content).
+    pub fn as_str(self) -> &'static str {
+        match self.resolve("") {
+            LineEnding::CRLF => "\r\n",
+            _ => "\n",
+        }
+    }
+}
+
+impl FromStr for LineEnding {
+    type Err = String;
+    fn from_str(s: &str) -> Result<LineEnding, String> {
+        match s {
+            "lf" => Ok(LineEnding::LF),
+            "crlf" => Ok(LineEnding::CRLF),
+            "native" => Ok(LineEnding::Native),
+            "consistent" => Ok(LineEnding::Consistent),
+            _ => Err(format!("unknown line ending: {}", s)),
+        }
+    }
+}
+
+/// Expand the tabs of a line to spaces (tab stops every `width` columns).
+pub fn to_spaces(line: Chars, width: usize) -> String {
     let mut result: Vec<char> = Vec::new();
-    let mut column: u8 = 0;
+    let mut column: usize = 0;
     for c in line {
         match c {
             '\t' => {
@@ -36,11 +100,49 @@ fn to_spaces(line: Chars, width: u8) -> String {
     result.into_iter().collect()
 }
 
-fn to_tabs(_: Chars, _: u8) -> String {
-    String::new()
+// convert the leading whitespace of a line into tabs (plus the spaces that
+// do not fill up a whole tab); whitespace after the first non-whitespace
+// character is left untouched
+fn to_tabs(line: Chars, width: usize) -> String {
+    let mut column: usize = 0;
+    let mut rest = line.clone();
+    for c in line {
+        match c {
+            ' ' => column += 1,
+            '\t' => column += width - column % width,
+            _ => break,
+        }
+        rest.next();
+    }
+    "\t".repeat(column / width) + &" ".repeat(column % width) + rest.as_str()
+}
+
+// like `to_tabs`, but the spaces after the last tab of the indentation are
+// kept for alignment; an indentation of spaces only is converted when it is
+// at least one tab wide
+fn to_smart_tabs(line: &str, width: usize) -> String {
+    let indent = &line[..line.len() - line.trim_start_matches([' ', '\t']).len()];
+    match indent.rfind('\t') {
+        Some(pos) => to_tabs(line[..=pos].chars(), width) + &line[pos + 1..],
+        None if indent.len() >= width => to_tabs(line.chars(), width),
+        None => line.to_string(),
+    }
+}
+
+/// the number of columns a line occupies when tabs are expanded to `width`
+pub fn expanded_width(line: &str, width: usize) -> usize {
+    line.chars().fold(0, |column, c| match c {
+        '\t' => column + width - column % width,
+        _ => column + 1,
+    })
 }
 
-pub fn space_tabs_conversion<S>(content: S, s: TabStrategy, line_ending: LineEnding) -> String
+pub fn space_tabs_conversion<S>(
+    content: S,
+    s: TabStrategy,
+    tab_width: usize,
+    line_ending: LineEnding,
+) -> String
 where
     S: Into<String>,
 {
@@ -48,14 +150,12 @@ where
         .into()
         .lines()
         .map(|line| match s {
-            TabStrategy::Untabify => to_spaces(line.chars(), 4),
-            TabStrategy::Tabify => to_tabs(line.chars(), 4),
+            TabStrategy::Untabify => to_spaces(line.chars(), tab_width),
+            TabStrategy::Tabify => to_tabs(line.chars(), tab_width),
+            TabStrategy::SmartTabs => to_smart_tabs(line, tab_width),
         })
         .collect();
-    let ending = match line_ending {
-        LineEnding::LF => "\n",
-        LineEnding::CRLF => "\r\n",
-    };
+    let ending = line_ending.as_str();
     let mut res = converted.join(ending);
     res.push_str(ending);
     res
@@ -68,10 +168,7 @@ where
     let s = input.into();
     let v: Vec<&str> = s.lines().map(|line| line.trim_end()).collect();
 
-    let ending = match line_ending {
-        LineEnding::LF => "\n",
-        LineEnding::CRLF => "\r\n",
-    };
+    let ending = line_ending.as_str();
     if s.ends_with('\n') {
         v.join(ending) + ending
     } else {
@@ -79,11 +176,92 @@ where
     }
 }
 
-pub fn replace_win_line_endings<S>(input: S) -> String
+/// Remove the blank (or whitespace only) lines at the end, unless the
+/// content has nothing else.
+pub fn remove_trailing_blank_lines<S>(input: S) -> String
+where
+    S: Into<String>,
+{
+    let s = input.into();
+    let mut lines: Vec<&str> = s.split_inclusive('\n').collect();
+    let blank = lines
+        .iter()
+        .rev()
+        .take_while(|l| l.trim().is_empty())
+        .count();
+    if blank < lines.len() {
+        lines.truncate(lines.len() - blank);
+    }
+    lines.concat()
+}
+
+/// Terminate the last line with a line ending (empty content stays empty).
+pub fn add_final_line_ending<S>(input: S, line_ending: &LineEnding) -> String
 where
     S: Into<String>,
 {
-    input.into().replace("\r\n", "\n")
+    let mut s = input.into();
+    if !s.is_empty() && !s.ends_with('\n') {
+        s.push_str(line_ending.as_str());
+    }
+    s
+}
+
+// the lines of a text ending with LF, CRLF or a lone CR (the last one may
+// have no line ending)
+fn terminated_lines(s: &str) -> impl Iterator<Item = &str> {
+    let mut rest = s;
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let bytes = rest.as_bytes();
+        let end = match bytes.iter().position(|b| *b == b'\n' || *b == b'\r') {
+            Some(i) if bytes[i] == b'\r' && bytes.get(i + 1) == Some(&b'\n') => i + 2,
+            Some(i) => i + 1,
+            None => rest.len(),
+        };
+        let (line, tail) = rest.split_at(end);
+        rest = tail;
+        Some(line)
+    })
+}
+
+/// Take the lines for which `use_cleaned` holds (1-based line numbers, lines
+/// end with LF) from `cleaned`, all others from `original`. Cleaning keeps the
+/// number of lines, counting the ones ending with a lone CR as well, except at
+/// the end: lines that are missing in `cleaned` (removed blank lines) are
+/// dropped if they are to be cleaned.
+pub fn merge_lines<F>(original: &str, cleaned: &str, use_cleaned: F) -> String
+where
+    F: Fn(usize) -> bool,
+{
+    let mut cleaned_lines = terminated_lines(cleaned);
+    let mut res = String::new();
+    for (i, line) in original.split_inclusive('\n').enumerate() {
+        // a line with lone CRs was cleaned as several lines
+        let parts = terminated_lines(line).count();
+        let cleaned: Vec<&str> = cleaned_lines.by_ref().take(parts).collect();
+        if use_cleaned(i + 1) {
+            res.extend(cleaned);
+        } else {
+            res.push_str(line);
+        }
+    }
+    res
+}
+
+/// End all lines (including the ones ending with a lone CR) with the same
+/// line ending.
+pub fn normalize_line_endings<S>(input: S, line_ending: LineEnding) -> String
+where
+    S: Into<String>,
+{
+    let lf = input.into().replace("\r\n", "\n").replace('\r', "\n");
+    match line_ending.as_str() {
+        "\n" => lf,
+        ending => lf.replace('\n', ending),
+    }
 }
 
 #[cfg(test)]
@@ -103,9 +281,70 @@ mod tests {
         assert!(cleaned.eq("1\n2"));
     }
     #[test]
+    fn test_merge_cleaned_lines() {
+        let original = "a \n\tb \nc \nd ";
+        let cleaned = "a\n    b\nc\nd\n";
+        assert_eq!(
+            merge_lines(original, cleaned, |l| l == 2 || l == 4),
+            "a \n    b\nc \nd\n"
+        );
+        assert_eq!(merge_lines(original, cleaned, |_| false), original);
+    }
+    #[test]
+    fn test_merge_lines_at_end_of_file() {
+        let original = "a \nb\n\n \n";
+        let cleaned = remove_trailing_blank_lines("a\nb\n\n\n");
+        assert_eq!(merge_lines(original, &cleaned, |l| l >= 3), "a \nb\n");
+        assert_eq!(merge_lines(original, &cleaned, |l| l == 4), "a \nb\n\n");
+        assert_eq!(merge_lines(original, &cleaned, |l| l == 1), "a\nb\n\n \n");
+        assert_eq!(merge_lines("a\nb", "a\nb\n", |l| l == 2), "a\nb\n");
+    }
+    #[test]
+    fn test_merge_lines_with_lone_cr() {
+        let original = "a\rb\nc \n";
+        let cleaned = normalize_line_endings("a\rb\nc\n", LineEnding::LF);
+        assert_eq!(merge_lines(original, &cleaned, |l| l == 2), "a\rb\nc\n");
+        assert_eq!(merge_lines(original, &cleaned, |l| l == 1), "a\nb\nc \n");
+    }
+    #[test]
+    fn test_remove_trailing_blank_lines() {
+        assert_eq!("a\n", remove_trailing_blank_lines("a\n\n \n\t\n"));
+        assert_eq!("a\r\n", remove_trailing_blank_lines("a\r\n\r\n"));
+        assert_eq!("a\n\nb", remove_trailing_blank_lines("a\n\nb"));
+        assert_eq!("\n\n", remove_trailing_blank_lines("\n\n"));
+    }
+    #[test]
+    fn test_add_final_line_ending() {
+        assert_eq!("a\n", add_final_line_ending("a", &LineEnding::LF));
+        assert_eq!("a\r\n", add_final_line_ending("a", &LineEnding::CRLF));
+        assert_eq!("a\n", add_final_line_ending("a\n", &LineEnding::CRLF));
+        assert_eq!("", add_final_line_ending("", &LineEnding::LF));
+    }
+    #[test]
+    fn test_resolve_line_ending() {
+        use LineEnding::*;
+        assert_eq!(CRLF, CRLF.resolve("a\nb\n"));
+        assert_eq!(CRLF, Consistent.resolve("a\r\nb\r\nc\n"));
+        assert_eq!(LF, Consistent.resolve("a\r\nb\n"));
+        assert_eq!(LF, Consistent.resolve(""));
+        assert_eq!(if cfg!(windows) { CRLF } else { LF }, Native.resolve(""));
+    }
+    #[test]
+    fn test_normalize_line_endings() {
+        let content = "1\r\n2\r3\n4";
+        assert_eq!(
+            "1\n2\n3\n4",
+            normalize_line_endings(content, LineEnding::LF)
+        );
+        assert_eq!(
+            "1\r\n2\r\n3\r\n4",
+            normalize_line_endings(content, LineEnding::CRLF)
+        );
+    }
+    #[test]
     fn test_clean_win_line_endings() {
         let content = "1\r\n2";
-        let cleaned = replace_win_line_endings(content);
+        let cleaned = normalize_line_endings(content, LineEnding::LF);
         assert!(cleaned.eq("1\n2"));
     }
     #[test]
@@ -136,6 +375,7 @@ mod tests {
         let cleaned_ln = space_tabs_conversion(
             text_with_tabs_newline,
             TabStrategy::Untabify,
+            4,
             LineEnding::LF,
         );
         assert_eq!(cleaned_ln, expected_ln);
@@ -143,6 +383,7 @@ mod tests {
         let cleaned_cr_ln = space_tabs_conversion(
             text_with_tabs_cr_ln,
             TabStrategy::Untabify,
+            4,
             LineEnding::CRLF,
         );
         assert_eq!(cleaned_cr_ln, expected_cr_ln);
@@ -157,6 +398,7 @@ mod tests {
         let cleaned_ln = space_tabs_conversion(
             text_with_tabs_newline,
             TabStrategy::Untabify,
+            4,
             LineEnding::LF,
         );
         assert_eq!(cleaned_ln, expected_ln);
@@ -164,6 +406,7 @@ mod tests {
         let cleaned_cr_ln = space_tabs_conversion(
             text_with_tabs_cr_ln,
             TabStrategy::Untabify,
+            4,
             LineEnding::CRLF,
         );
         assert_eq!(cleaned_cr_ln, expected_cr_ln);
@@ -179,6 +422,7 @@ mod tests {
         let cleaned_ln = space_tabs_conversion(
             text_with_tabs_newline,
             TabStrategy::Untabify,
+            4,
             LineEnding::LF,
         );
         assert_eq!(cleaned_ln, expected_ln);
@@ -186,6 +430,7 @@ mod tests {
         let cleaned_cr_ln = space_tabs_conversion(
             text_with_tabs_cr_ln,
             TabStrategy::Untabify,
+            4,
             LineEnding::CRLF,
         );
         assert_eq!(cleaned_cr_ln, expected_cr_ln);
@@ -226,4 +471,62 @@ mod tests {
         let converted = to_spaces(line, 2);
         assert_eq!(converted, "   foo");
     }
+    #[test]
+    fn test_wide_tabs_to_spaces() {
+        let line = "\tfoo".chars();
+        let converted = to_spaces(line, 300);
+        assert_eq!(converted.len(), 303);
+        assert!(converted.ends_with(" foo"));
+    }
+    #[test]
+    fn test_expanded_width() {
+        assert_eq!(expanded_width("foo", 4), 3);
+        assert_eq!(expanded_width("\tfoo", 4), 7);
+        assert_eq!(expanded_width("\tfoo", 8), 11);
+        assert_eq!(expanded_width("ab\tc", 4), 5);
+        assert_eq!(expanded_width("\t", 256), 256);
+    }
+    #[test]
+    fn test_leading_spaces_to_tabs() {
+        let line = "        foo".chars();
+        let converted = to_tabs(line, 4);
+        assert_eq!(converted, "\t\tfoo");
+    }
+    #[test]
+    fn test_leading_spaces_to_tabs_with_remainder() {
+        let line = "      foo".chars();
+        let converted = to_tabs(line, 4);
+        assert_eq!(converted, "\t  foo");
+    }
+    #[test]
+    fn test_mixed_indentation_to_tabs() {
+        let line = "  \t  \tfoo".chars();
+        let converted = to_tabs(line, 4);
+        assert_eq!(converted, "\t\tfoo");
+    }
+    #[test]
+    fn test_to_tabs_leaves_inner_spaces_alone() {
+        let line = "    foo    bar\t baz".chars();
+        let converted = to_tabs(line, 4);
+        assert_eq!(converted, "\tfoo    bar\t baz");
+    }
+    #[test]
+    fn test_smart_tabs_keep_alignment() {
+        assert_eq!("\t\t  x", to_smart_tabs("\t  \t  x", 4));
+        assert_eq!("\t  x  y", to_smart_tabs("\t  x  y", 4));
+        assert_eq!("\t  x", to_smart_tabs("      x", 4));
+        assert_eq!(" * x", to_smart_tabs(" * x", 4));
+    }
+    #[test]
+    fn test_spaces_to_tabs() {
+        let text = ["    foo", "        bar", "baz"].join("\n");
+        let cleaned = space_tabs_conversion(text, TabStrategy::Tabify, 4, LineEnding::LF);
+        assert_eq!(cleaned, "\tfoo\n\t\tbar\nbaz\n");
+    }
+    #[test]
+    fn test_tabs_to_spaces_with_width() {
+        let text = ["\tfoo", "\t\tbar"].join("\n");
+        let cleaned = space_tabs_conversion(text, TabStrategy::Untabify, 2, LineEnding::LF);
+        assert_eq!(cleaned, "  foo\n    bar\n");
+    }
 }
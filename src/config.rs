@@ -1,61 +1,496 @@
+use crate::{
+    check::Rule,
+    clean::{Bom, LineEnding},
+    search,
+};
 use anyhow::{anyhow, Error};
 use regex::Regex;
-use serde_derive::Deserialize;
-use std::{self, fs};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    self,
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use toml::{value::Table, Value};
+use toml_edit::TableLike;
 
-use std::{io::Read, path::PathBuf};
-use toml;
+/// Name of the config files.
+pub const CFG_FILE_NAME: &str = ".enforcer";
+pub const DEFAULT_TAB_WIDTH: usize = 4;
+pub const DEFAULT_THREADS: usize = 4;
 
-const DEFAULT_CFG_FILE: &str = "./.enforcer";
-
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
 pub struct EnforcerCfg {
+    // required, but nested config files can leave them out
+    #[serde(default)]
     pub ignore: Vec<String>,
+    #[serde(default)]
+    pub endings: Vec<String>,
+    /// width used to expand tabs (cleaning and line length measurement)
+    #[serde(default)]
+    pub tab_width: Option<usize>,
+    /// tab widths for specific file endings (e.g. `".c" = 8`)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tab_widths: BTreeMap<String, usize>,
+    #[serde(default)]
+    pub rules: RulesCfg,
+    #[serde(default)]
+    pub run: RunCfg,
+    /// rule settings for some of the files (`[[override]]` sections)
+    #[serde(default, rename = "override", skip_serializing_if = "Vec::is_empty")]
+    pub overrides: Vec<Override>,
+    /// the config files this config was merged from
+    #[serde(skip)]
+    pub files: Vec<PathBuf>,
+}
+
+/// The `[rules]` table: what is checked for all files (the command line
+/// options take precedence).
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct RulesCfg {
+    /// lines are not checked for their length if not set
+    pub max_line_length: Option<usize>,
+    /// indentation is a multiple of it, not checked if not set
+    pub indent_width: Option<usize>,
+    /// indent with tabs instead of spaces (`--tabs`)
+    pub allow_tabs: bool,
+    /// with `allow_tabs`: tabs for indentation, spaces for alignment
+    pub smart_tabs: bool,
+    pub line_ending: LineEnding,
+    /// whether files start with a UTF-8 byte order mark
+    pub bom: Bom,
+    pub check_illegal_chars: bool,
+    pub check_trailing_spaces: bool,
+    /// the last line ends with a line ending
+    pub check_final_newline: bool,
+    /// no blank lines at the end of a file
+    pub check_trailing_blank_lines: bool,
+    /// tabs and spaces are not mixed in the indentation (off by default)
+    pub check_mixed_indentation: bool,
+    /// take the rules from the matching `.editorconfig` sections
+    pub editorconfig: bool,
+}
+
+impl Default for RulesCfg {
+    fn default() -> Self {
+        RulesCfg {
+            max_line_length: None,
+            indent_width: None,
+            allow_tabs: false,
+            smart_tabs: false,
+            line_ending: LineEnding::LF,
+            bom: Bom::Forbid,
+            check_illegal_chars: true,
+            check_trailing_spaces: true,
+            check_final_newline: true,
+            check_trailing_blank_lines: true,
+            check_mixed_indentation: false,
+            editorconfig: false,
+        }
+    }
+}
+
+impl RulesCfg {
+    /// The rules switched off in the config.
+    pub fn disabled_rules(&self) -> Vec<Rule> {
+        let mut res = Vec::new();
+        if !self.check_illegal_chars {
+            res.push(Rule::IllegalCharacters);
+        }
+        if !self.check_trailing_spaces {
+            res.push(Rule::TrailingSpaces);
+        }
+        if !self.check_final_newline {
+            res.push(Rule::MissingFinalNewline);
+        }
+        if !self.check_trailing_blank_lines {
+            res.push(Rule::TrailingBlankLines);
+        }
+        if !self.check_mixed_indentation {
+            res.push(Rule::MixedIndentation);
+        }
+        res
+    }
+}
+
+/// The `[run]` table: how enforcer runs.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct RunCfg {
+    /// 0 uses one thread per CPU (at most 12)
+    pub threads: usize,
+    pub color: bool,
+}
+
+impl RunCfg {
+    pub fn thread_count(&self) -> usize {
+        if self.threads == 0 {
+            std::cmp::min(12, num_cpus::get())
+        } else {
+            self.threads
+        }
+    }
+}
+
+impl Default for RunCfg {
+    fn default() -> Self {
+        RunCfg {
+            threads: DEFAULT_THREADS,
+            color: false,
+        }
+    }
+}
+
+/// Rule settings for the files matching one of the `paths` globs or ending
+/// with one of the `endings`.
+#[derive(Debug, Default, Clone, Deserialize, Serialize, PartialEq)]
+pub struct Override {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub paths: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub endings: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub enable: Vec<Rule>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub disable: Vec<Rule>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_line_length: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub indent_width: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tabs_allowed: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line_ending: Option<LineEnding>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bom: Option<Bom>,
+}
+
+impl Override {
+    pub fn matches(&self, path: &Path) -> bool {
+        search::matches_any(path, &self.paths) || search::has_ending(path, &self.endings)
+    }
+}
+
+impl EnforcerCfg {
+    /// The tab width for a file: an entry in `tab_widths` whose ending matches
+    /// the file name (the longest one wins), then `tab_width`, then the default.
+    pub fn tab_width_for(&self, path: &Path) -> usize {
+        let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+        self.tab_widths
+            .iter()
+            .filter(|(ending, _)| file_name.ends_with(ending.as_str()))
+            .max_by_key(|(ending, _)| ending.len())
+            .map(|(_, width)| *width)
+            .or(self.tab_width)
+            .unwrap_or(DEFAULT_TAB_WIDTH)
+    }
+
+    /// The overrides that apply to a file, later ones take precedence.
+    pub fn overrides_for<'a>(&'a self, path: &'a Path) -> impl Iterator<Item = &'a Override> {
+        self.overrides.iter().filter(move |o| o.matches(path))
+    }
 }
 
 pub fn s(x: &str) -> String {
     x.to_string()
 }
 
-fn load_default_cfg_file() -> Result<fs::File, Error> {
-    let p = PathBuf::from(DEFAULT_CFG_FILE);
-    if !p.as_path().exists() {
-        println!("default config file {:?} does not exist!", DEFAULT_CFG_FILE);
-        Err(anyhow!("default config file missing"))
-    } else {
-        Ok(fs::File::open(p)?)
-    }
+/// Read the config from a file.
+pub fn get_cfg(config_file: &Path) -> Result<EnforcerCfg, Error> {
+    let content = fs::read_to_string(config_file)
+        .map_err(|e| anyhow!("could not read config file {:?}: {}", config_file, e))?;
+    parse_config(&content).map_err(|e| anyhow!("{}: {}", config_file.display(), e))
 }
 
-pub fn get_cfg(config_file: &Option<PathBuf>) -> EnforcerCfg {
-    let read_enforcer_config = |cnfg: &Option<PathBuf>| -> Result<EnforcerCfg, Error> {
-        let mut cfg_file = match *cnfg {
-            Some(ref p) => {
-                if !p.as_path().exists() {
-                    println!("provided file {:?} does not exist!", p);
-                }
-                fs::File::open(p)?
+fn fallback_cfg(e: &Error) -> EnforcerCfg {
+    eprintln!("{}", e);
+    eprintln!("taking default configuration: {:?}", default_cfg());
+    default_cfg()
+}
+
+type Adjust = Box<dyn Fn(EnforcerCfg) -> EnforcerCfg + Send + Sync>;
+
+/// The configuration for every directory: all `.enforcer` files from the
+/// repository root down to the directory are merged, nested ones extend or
+/// override the settings of their parents for the files below them.
+pub struct CfgTree {
+    /// a config file given on the command line is used everywhere
+    fixed: Option<Arc<EnforcerCfg>>,
+    /// take the default config instead of failing on missing or broken files
+    fallback: bool,
+    /// applied to every merged config (the command line options)
+    adjust: Adjust,
+    cache: Mutex<CfgCache>,
+}
+
+#[derive(Default)]
+struct CfgCache {
+    /// parsed config files
+    files: HashMap<PathBuf, Result<Table, String>>,
+    /// merged configs by the config files they consist of
+    merged: HashMap<Vec<PathBuf>, Arc<EnforcerCfg>>,
+    /// config and path relative to the outermost config file per directory
+    dirs: HashMap<PathBuf, (Arc<EnforcerCfg>, Option<PathBuf>)>,
+    /// the problems with config files found so far
+    errors: Vec<String>,
+}
+
+impl CfgTree {
+    /// With `fallback` missing or broken config files are reported and the
+    /// default config is taken, without they are errors.
+    pub fn new<F>(config_file: Option<&Path>, fallback: bool, adjust: F) -> Result<CfgTree, Error>
+    where
+        F: Fn(EnforcerCfg) -> EnforcerCfg + Send + Sync + 'static,
+    {
+        let fixed = match config_file.map(get_cfg) {
+            Some(Ok(cfg)) => Some(cfg),
+            Some(Err(e)) if fallback => Some(fallback_cfg(&e)),
+            Some(Err(e)) => return Err(e),
+            None => None,
+        };
+        Ok(CfgTree {
+            fixed: fixed.map(|cfg| Arc::new(adjust(cfg))),
+            fallback,
+            adjust: Box::new(adjust),
+            cache: Mutex::default(),
+        })
+    }
+
+    /// Fails with all problems of the config files that were needed so far.
+    pub fn check(&self) -> Result<(), Error> {
+        let cache = self.cache.lock().expect("config cache");
+        if cache.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!("{}", cache.errors.join("\n")))
+        }
+    }
+
+    /// The config for the files in a directory.
+    pub fn cfg_in(&self, dir: &Path) -> Arc<EnforcerCfg> {
+        self.dir_entry(dir).0
+    }
+
+    /// The config for a file (or directory), which is the one of the
+    /// directory it is in.
+    pub fn cfg_for(&self, path: &Path) -> Arc<EnforcerCfg> {
+        self.lookup(path).0
+    }
+
+    /// The config for a path and the path relative to the directory of the
+    /// outermost config file, which the globs in the config are matched against.
+    pub fn lookup(&self, path: &Path) -> (Arc<EnforcerCfg>, PathBuf) {
+        let (cfg, rel_dir) = self.dir_entry(parent_dir(path));
+        let rel = match (rel_dir, path.file_name()) {
+            (Some(dir), Some(name)) => dir.join(name),
+            _ => path.to_path_buf(),
+        };
+        (cfg, rel)
+    }
+
+    fn dir_entry(&self, dir: &Path) -> (Arc<EnforcerCfg>, Option<PathBuf>) {
+        if let Some(cfg) = &self.fixed {
+            return (cfg.clone(), None);
+        }
+        let mut cache = self.cache.lock().expect("config cache");
+        if let Some(entry) = cache.dirs.get(dir) {
+            return entry.clone();
+        }
+        let canonical = search::resolved(dir);
+        let files = canonical.as_deref().map(config_files).unwrap_or_default();
+        let rel_dir = match (&canonical, files.first().and_then(|f| f.parent())) {
+            (Some(dir), Some(root)) => dir.strip_prefix(root).ok().map(Path::to_path_buf),
+            _ => None,
+        };
+        let cfg = self.merged(&mut cache, files, dir);
+        let entry = (cfg, rel_dir);
+        cache.dirs.insert(dir.to_path_buf(), entry.clone());
+        entry
+    }
+
+    fn merged(&self, cache: &mut CfgCache, files: Vec<PathBuf>, dir: &Path) -> Arc<EnforcerCfg> {
+        if let Some(cfg) = cache.merged.get(&files) {
+            return cfg.clone();
+        }
+        let merged = if files.is_empty() {
+            Err(anyhow!(
+                "no {} file found in {:?} or its parent directories \
+                 (use --config-fallback to take the default configuration)",
+                CFG_FILE_NAME,
+                dir
+            ))
+        } else {
+            self.merge_files(cache, &files)
+        };
+        let cfg = match merged {
+            Ok(cfg) => EnforcerCfg {
+                files: files.clone(),
+                ..cfg
+            },
+            Err(e) if self.fallback => fallback_cfg(&e),
+            Err(e) => {
+                cache.errors.push(e.to_string());
+                default_cfg()
             }
-            None => load_default_cfg_file()?,
         };
-        let mut buffer = String::new();
-        cfg_file.read_to_string(&mut buffer)?;
-        parse_config(&buffer[..])
+        let cfg = Arc::new((self.adjust)(cfg));
+        cache.merged.insert(files, cfg.clone());
+        cfg
+    }
+
+    fn merge_files(&self, cache: &mut CfgCache, files: &[PathBuf]) -> Result<EnforcerCfg, Error> {
+        let root = files.first().and_then(|f| f.parent());
+        let mut merged = Table::new();
+        for f in files {
+            let layer = cache.files.entry(f.clone()).or_insert_with(|| {
+                let layer = read_layer(f).map_err(|e| e.to_string());
+                if let (Err(e), true) = (&layer, self.fallback) {
+                    eprintln!("{}, skipping it", e);
+                }
+                layer
+            });
+            let mut layer = match layer.clone() {
+                Ok(layer) => layer,
+                Err(_) if self.fallback => continue,
+                Err(e) => return Err(anyhow!(e)),
+            };
+            let dir = f.parent().and_then(|d| d.strip_prefix(root?).ok());
+            if let Some(dir) = dir.filter(|d| !d.as_os_str().is_empty()) {
+                rebase(&mut layer, dir);
+            }
+            merge(&mut merged, layer);
+        }
+        require_keys(&merged).map_err(|e| anyhow!("{:?}: {}", files[0], e))?;
+        checked(Value::Table(merged).try_into().map_err(Error::from))
+    }
+}
+
+impl search::Selection for CfgTree {
+    fn is_ignored(&self, path: &Path) -> bool {
+        let (cfg, rel) = self.lookup(path);
+        search::matches_any(&rel, &cfg.ignore)
+    }
+    fn has_ending(&self, file: &Path) -> bool {
+        search::has_ending(file, &self.cfg_for(file).endings)
+    }
+}
+
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    }
+}
+
+/// The config files from the repository root (or the file system root) down
+/// to a directory.
+pub fn config_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for d in dir.ancestors() {
+        let f = d.join(CFG_FILE_NAME);
+        if f.is_file() {
+            files.push(f);
+        }
+        if d.join(".git").exists() {
+            break;
+        }
+    }
+    files.reverse();
+    files
+}
+
+fn read_layer(file: &Path) -> Result<Table, Error> {
+    fs::read_to_string(file)
+        .map_err(Error::from)
+        .and_then(|content| parse_layer(&content))
+        .map_err(|e| anyhow!("{}: {}", file.display(), e))
+}
+
+// nested config files extend the `ignore` globs and the overrides of their
+// parents, everything else is replaced (tables key by key)
+fn merge(base: &mut Table, layer: Table) {
+    for (key, value) in layer {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(b)), Value::Table(l)) => merge(b, l),
+            (Some(Value::Array(b)), Value::Array(l)) if key == "ignore" || key == "override" => {
+                b.extend(l)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+// the globs of a nested config file are relative to its directory
+fn rebase(layer: &mut Table, dir: &Path) {
+    let prefixed = |glob: &mut Value, fix: bool| {
+        if let Value::String(g) = glob {
+            let g2 = if fix { suggestion(g) } else { g.clone() };
+            *g = format!("{}/{}", dir.to_string_lossy(), g2);
+        }
     };
-    match read_enforcer_config(config_file) {
-        Ok(c) => c,
-        _ => {
-            println!("taking default configuration: {:?}", default_cfg());
-            default_cfg()
+    if let Some(Value::Array(ignores)) = layer.get_mut("ignore") {
+        ignores.iter_mut().for_each(|g| prefixed(g, true));
+    }
+    if let Some(Value::Array(overrides)) = layer.get_mut("override") {
+        for o in overrides.iter_mut() {
+            if let Some(Value::Array(paths)) = o.get_mut("paths") {
+                paths.iter_mut().for_each(|g| prefixed(g, false));
+            }
+        }
+    }
+}
+
+/// Rewrite the old style `ignore` patterns of a config file with the
+/// suggested globs (`.git` => `**/.git`), comments and formatting are kept.
+/// Returns `None` if nothing needs to change.
+pub fn migrate(content: &str) -> Result<Option<String>, Error> {
+    let mut doc = content
+        .parse::<toml_edit::DocumentMut>()
+        .map_err(|e| anyhow!("{}", e))?;
+    let mut changed = false;
+    if let Some(ignores) = doc.get_mut("ignore").and_then(|i| i.as_array_mut()) {
+        for glob in ignores.iter_mut() {
+            if let toml_edit::Value::String(g) = glob {
+                let fixed = suggestion(g.value());
+                if &fixed != g.value() {
+                    let decor = g.decor().clone();
+                    *g = toml_edit::Formatted::new(fixed);
+                    *g.decor_mut() = decor;
+                    changed = true;
+                }
+            }
+        }
+    }
+    Ok(if changed { Some(doc.to_string()) } else { None })
+}
+
+/// Show the lines that `migrate` changed. Only values are replaced, so
+/// the lines of both versions correspond to each other.
+pub fn migration_diff(file: &Path, old: &str, new: &str) -> String {
+    let mut diff = format!("--- {0}\n+++ {0}\n", file.display());
+    for (nr, (o, n)) in old.lines().zip(new.lines()).enumerate() {
+        if o != n {
+            diff += &format!("@@ line {} @@\n-{}\n+{}\n", nr + 1, o, n);
         }
     }
+    diff
 }
 
 fn default_cfg() -> EnforcerCfg {
     EnforcerCfg {
         ignore: vec![s("**/.git"), s("**/.bake"), s("**/.repo")],
         endings: vec![s(".c"), s(".cpp"), s(".h")],
+        tab_width: None,
+        tab_widths: BTreeMap::new(),
+        rules: RulesCfg::default(),
+        run: RunCfg::default(),
+        overrides: Vec::new(),
+        files: Vec::new(),
     }
 }
 
@@ -67,6 +502,12 @@ fn fix_config(cfg: &EnforcerCfg) -> EnforcerCfg {
             .map(|i| suggestion(i))
             .collect::<Vec<String>>(),
         endings: cfg.endings.clone(),
+        tab_width: cfg.tab_width,
+        tab_widths: cfg.tab_widths.clone(),
+        rules: cfg.rules.clone(),
+        run: cfg.run.clone(),
+        overrides: cfg.overrides.clone(),
+        files: cfg.files.clone(),
     }
 }
 
@@ -89,14 +530,215 @@ fn suggestion(s: &str) -> String {
     }
 }
 
+fn validate_config(cfg: EnforcerCfg) -> Result<EnforcerCfg, Error> {
+    if cfg.tab_width == Some(0) || cfg.tab_widths.values().any(|w| *w == 0) {
+        return Err(anyhow!("tab width needs to be at least 1"));
+    }
+    let indent_widths = cfg.overrides.iter().map(|o| o.indent_width);
+    if std::iter::once(cfg.rules.indent_width)
+        .chain(indent_widths)
+        .any(|w| w == Some(0))
+    {
+        return Err(anyhow!("indent width needs to be at least 1"));
+    }
+    for o in &cfg.overrides {
+        if o.paths.is_empty() && o.endings.is_empty() {
+            return Err(anyhow!("an override needs `paths` or `endings`"));
+        }
+        if let Some(p) = o.paths.iter().find(|p| glob::Pattern::new(p).is_err()) {
+            return Err(anyhow!("invalid glob in override: {:?}", p));
+        }
+    }
+    Ok(cfg)
+}
+
 pub fn parse_config(input: &str) -> Result<EnforcerCfg, Error> {
-    match toml::from_str(input) {
+    let layer = parse_layer(input)?;
+    require_keys(&layer)?;
+    checked(Value::Table(layer).try_into().map_err(Error::from))
+}
+
+// parse a single config file, all keys need to be known and have the right type
+fn parse_layer(input: &str) -> Result<Table, Error> {
+    let layer: Table = toml::from_str(input)?;
+    let mut unknown: Vec<(Option<(usize, usize)>, String)> = unknown_keys(&layer)
+        .iter()
+        .map(|k| (k.position(input), k.message(input)))
+        .collect();
+    unknown.sort();
+    let unknown: Vec<String> = unknown.into_iter().map(|(_, msg)| msg).collect();
+    if !unknown.is_empty() {
+        return Err(anyhow!("{}", unknown.join("\n")));
+    }
+    toml::from_str::<EnforcerCfg>(input)
+        .map_err(Error::from)
+        .and_then(validate_config)?;
+    Ok(layer)
+}
+
+fn require_keys(cfg: &Table) -> Result<(), Error> {
+    match ["ignore", "endings"]
+        .iter()
+        .find(|k| !cfg.contains_key(**k))
+    {
+        Some(key) => Err(anyhow!("missing key `{}`", key)),
+        None => Ok(()),
+    }
+}
+
+const CFG_KEYS: &[&str] = &[
+    "ignore",
+    "endings",
+    "tab_width",
+    "tab_widths",
+    "rules",
+    "run",
+    "override",
+];
+const RULES_KEYS: &[&str] = &[
+    "max_line_length",
+    "indent_width",
+    "allow_tabs",
+    "smart_tabs",
+    "line_ending",
+    "bom",
+    "check_illegal_chars",
+    "check_trailing_spaces",
+    "check_final_newline",
+    "check_trailing_blank_lines",
+    "check_mixed_indentation",
+    "editorconfig",
+];
+const RUN_KEYS: &[&str] = &["threads", "color"];
+const OVERRIDE_KEYS: &[&str] = &[
+    "paths",
+    "endings",
+    "enable",
+    "disable",
+    "max_line_length",
+    "indent_width",
+    "tabs_allowed",
+    "line_ending",
+    "bom",
+];
+
+// a key enforcer does not know
+struct UnknownKey<'a> {
+    key: &'a str,
+    /// the table it is in (empty for the top level) and the index for
+    /// arrays of tables
+    table: &'static str,
+    index: usize,
+    known: &'static [&'static str],
+}
+
+impl UnknownKey<'_> {
+    fn position(&self, input: &str) -> Option<(usize, usize)> {
+        key_position(input, self.table, self.index, self.key)
+    }
+
+    fn message(&self, input: &str) -> String {
+        let mut msg = format!("unknown key `{}`", self.key);
+        if !self.table.is_empty() {
+            msg += &format!(" in [{}]", self.table);
+        }
+        if let Some((line, column)) = self.position(input) {
+            msg += &format!(" at line {} column {}", line, column);
+        }
+        if let Some(known) = did_you_mean(self.key, self.known) {
+            msg += &format!(", did you mean `{}`?", known);
+        }
+        msg
+    }
+}
+
+fn unknown_keys(cfg: &Table) -> Vec<UnknownKey<'_>> {
+    let mut tables = vec![(cfg, "", 0, CFG_KEYS)];
+    for (key, value) in cfg {
+        match (key.as_str(), value) {
+            ("rules", Value::Table(t)) => tables.push((t, "rules", 0, RULES_KEYS)),
+            ("run", Value::Table(t)) => tables.push((t, "run", 0, RUN_KEYS)),
+            ("override", Value::Array(overrides)) => {
+                for (i, o) in overrides.iter().enumerate() {
+                    if let Value::Table(t) = o {
+                        tables.push((t, "override", i, OVERRIDE_KEYS));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    let mut res = Vec::new();
+    for (t, table, index, known) in tables {
+        for key in t.keys().filter(|k| !known.contains(&k.as_str())) {
+            res.push(UnknownKey {
+                key,
+                table,
+                index,
+                known,
+            });
+        }
+    }
+    res
+}
+
+// line and column (1-based) of a key in a table of a config file (the n-th
+// one for arrays of tables), as the TOML parser found it
+fn key_position(input: &str, table: &str, index: usize, key: &str) -> Option<(usize, usize)> {
+    let doc = toml_edit::ImDocument::parse(input).ok()?;
+    let root = doc.as_table();
+    let parent: &dyn TableLike = if table.is_empty() {
+        root
+    } else {
+        let item = root.get(table)?;
+        match item.as_array_of_tables() {
+            Some(tables) => tables.get(index)?,
+            None => item.as_table_like()?,
+        }
+    };
+    let before = &input[..parent.key(key)?.span()?.start];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    Some((
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    ))
+}
+
+// the known key closest to a misspelled one
+fn did_you_mean(key: &str, known: &[&'static str]) -> Option<&'static str> {
+    known
+        .iter()
+        .map(|k| (edit_distance(key, k), *k))
+        .filter(|(d, _)| *d <= std::cmp::max(2, key.len() / 3))
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, k)| k)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substituted = prev + if ca == *cb { 0 } else { 1 };
+            prev = row[j + 1];
+            row[j + 1] = (row[j] + 1).min(prev + 1).min(substituted);
+        }
+    }
+    row[b.len()]
+}
+
+// validate a parsed config and fix old style globs
+fn checked(parsed: Result<EnforcerCfg, Error>) -> Result<EnforcerCfg, Error> {
+    match parsed.and_then(validate_config) {
         Ok(config) => {
             let suggested = fix_config(&config);
             if suggested.ignore != config.ignore {
-                println!(
+                eprintln!(
                     "old style config found. we will assume this:\n{:?}\nconsider \
-                          changing it! (see http://www.globtester.com/ for reference)",
+                          changing it with `enforcer config migrate`! \
+                          (see http://www.globtester.com/ for reference)",
                     suggested
                 );
                 Ok(suggested)
@@ -142,7 +784,18 @@ pub fn parse_config(input: &str) -> Result<EnforcerCfg, Error> {
 
 #[cfg(test)]
 mod tests {
-    use super::{parse_config, s, suggestion, EnforcerCfg};
+    use super::{
+        default_cfg, migrate, migration_diff, parse_config, s, suggestion, CfgTree, EnforcerCfg,
+        Override, RulesCfg, RunCfg, CFG_KEYS, DEFAULT_TAB_WIDTH, DEFAULT_THREADS, OVERRIDE_KEYS,
+        RULES_KEYS, RUN_KEYS,
+    };
+    use crate::search::Selection;
+    use crate::{
+        check::Rule,
+        clean::{Bom, LineEnding},
+    };
+    use std::fs;
+    use std::{collections::BTreeMap, path::Path};
 
     #[test]
     fn test_load_simple_config() {
@@ -152,11 +805,282 @@ mod tests {
         let expected = EnforcerCfg {
             ignore: vec![s("**/.git"), s("**/.repo")],
             endings: vec![s(".c"), s(".cpp"), s(".h")],
+            tab_width: None,
+            tab_widths: BTreeMap::new(),
+            rules: RulesCfg::default(),
+            run: RunCfg::default(),
+            overrides: Vec::new(),
+            files: Vec::new(),
         };
         assert_eq!(expected.ignore, cfg.ignore);
         assert_eq!(expected, cfg);
     }
     #[test]
+    fn test_tab_widths() {
+        let c = r#"
+            ignore = []
+            endings = [".c", ".js", ".cpp"]
+            tab_width = 3
+            [tab_widths]
+            ".c" = 8
+            ".js" = 2
+            ".min.js" = 300
+        "#;
+        let cfg = parse_config(c).unwrap();
+        assert_eq!(cfg.tab_width_for(Path::new("src/a.c")), 8);
+        assert_eq!(cfg.tab_width_for(Path::new("src/a.js")), 2);
+        assert_eq!(cfg.tab_width_for(Path::new("src/a.min.js")), 300);
+        assert_eq!(cfg.tab_width_for(Path::new("src/a.cpp")), 3);
+        let c = r#"
+            ignore = []
+            endings = [".c"]
+        "#;
+        let cfg = parse_config(c).unwrap();
+        assert_eq!(cfg.tab_width_for(Path::new("a.c")), DEFAULT_TAB_WIDTH);
+    }
+    #[test]
+    fn test_zero_tab_width_is_rejected() {
+        let c = r#"
+            ignore = []
+            endings = [".c"]
+            tab_width = 0
+        "#;
+        assert!(parse_config(c).is_err());
+    }
+    #[test]
+    fn test_overrides() {
+        let c = r#"
+            ignore = []
+            endings = [".c", ".h", ".mk", "Makefile", ".bat"]
+            [[override]]
+            endings = [".mk", "Makefile"]
+            tabs_allowed = true
+            [[override]]
+            paths = ["generated/**"]
+            disable = ["LINE_TOO_LONG"]
+            [[override]]
+            endings = [".bat"]
+            line_ending = "crlf"
+            max_line_length = 200
+        "#;
+        let cfg = parse_config(c).unwrap();
+        let applied = |p: &str| cfg.overrides_for(Path::new(p)).count();
+        assert_eq!(applied("src/Makefile"), 1);
+        assert_eq!(applied("./src/rules.mk"), 1);
+        assert_eq!(applied("./generated/a/b.h"), 1);
+        assert_eq!(applied("src/generated.h"), 0);
+        assert_eq!(applied("src/a.c"), 0);
+        let bat = cfg.overrides_for(Path::new("run.bat")).next().unwrap();
+        assert_eq!(bat.line_ending, Some(LineEnding::CRLF));
+        assert_eq!(bat.max_line_length, Some(200));
+        assert_eq!(cfg.overrides[1].disable, vec![Rule::LineTooLong]);
+    }
+    #[test]
+    fn test_rules_and_run() {
+        let c = r#"
+            ignore = []
+            endings = [".c"]
+            [rules]
+            max_line_length = 120
+            line_ending = "crlf"
+            check_illegal_chars = false
+            check_mixed_indentation = true
+            [run]
+            color = true
+        "#;
+        let cfg = parse_config(c).unwrap();
+        assert_eq!(cfg.rules.max_line_length, Some(120));
+        assert_eq!(cfg.rules.line_ending, LineEnding::CRLF);
+        assert!(!cfg.rules.allow_tabs);
+        assert_eq!(cfg.rules.disabled_rules(), vec![Rule::IllegalCharacters]);
+        assert_eq!(cfg.run.threads, DEFAULT_THREADS);
+        assert!(cfg.run.color);
+        // the effective config can be printed and read back
+        let printed = toml::to_string_pretty(&cfg).unwrap();
+        assert_eq!(parse_config(&printed).unwrap(), cfg);
+    }
+    #[test]
+    fn test_nested_configs() {
+        let dir = std::env::temp_dir().join(format!("enforcer_cfg_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join(".git")).unwrap();
+        fs::create_dir_all(dir.join("sub/deeper")).unwrap();
+        let root_cfg = r#"
+            ignore = ["gen/**"]
+            endings = [".c"]
+            [rules]
+            max_line_length = 80
+            check_illegal_chars = false
+        "#;
+        fs::write(dir.join(".enforcer"), root_cfg).unwrap();
+        let sub_cfg = r#"
+            ignore = ["tmp/**"]
+            endings = [".c", ".py"]
+            [rules]
+            max_line_length = 100
+            [[override]]
+            paths = ["deeper/**"]
+            tabs_allowed = true
+        "#;
+        fs::write(dir.join("sub/.enforcer"), sub_cfg).unwrap();
+
+        let tree = CfgTree::new(None, false, |cfg| cfg).unwrap();
+        let root = tree.cfg_for(&dir.join("a.c"));
+        assert_eq!(root.rules.max_line_length, Some(80));
+        assert_eq!(root.endings, vec![s(".c")]);
+        let sub = tree.cfg_for(&dir.join("sub/deeper/a.c"));
+        assert_eq!(sub.rules.max_line_length, Some(100));
+        assert!(!sub.rules.check_illegal_chars);
+        assert_eq!(sub.files.len(), 2);
+        assert_eq!(sub.ignore, vec![s("gen/**"), s("sub/tmp/**")]);
+        assert_eq!(sub.overrides_for(Path::new("sub/deeper/a.c")).count(), 1);
+        assert_eq!(
+            tree.lookup(&dir.join("sub/deeper/a.c")).1,
+            Path::new("sub/deeper/a.c")
+        );
+
+        assert!(tree.is_ignored(&dir.join("gen/a.c")));
+        assert!(!tree.is_ignored(&dir.join("sub/gen/a.c")));
+        assert!(tree.is_ignored(&dir.join("sub/tmp/a.c")));
+        assert!(!tree.is_ignored(&dir.join("tmp/a.c")));
+        assert!(tree.has_ending(&dir.join("sub/a.py")));
+        assert!(!tree.has_ending(&dir.join("a.py")));
+        assert!(tree.check().is_ok());
+
+        fs::create_dir_all(dir.join("sub/broken")).unwrap();
+        fs::write(dir.join("sub/broken/.enforcer"), "endigns = []").unwrap();
+        tree.cfg_for(&dir.join("sub/broken/b.c"));
+        let err = tree.check().unwrap_err().to_string();
+        assert!(err.contains("did you mean `endings`?"), "{}", err);
+        let _ = fs::remove_dir_all(&dir);
+    }
+    #[test]
+    fn test_unknown_keys() {
+        let c = r#"ignore = []
+endings = [".c"]
+  ignores = []
+
+[rules]
+max_line_lenght = 80
+
+[[override]]
+paths = ["a/**"]
+
+[[override]]
+endings = [".h"]
+tabs_alowed = true
+completely_different = 1
+"#;
+        let err = parse_config(c).unwrap_err().to_string();
+        let lines: Vec<&str> = err.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "unknown key `ignores` at line 3 column 3, did you mean `ignore`?",
+                "unknown key `max_line_lenght` in [rules] at line 6 column 1, \
+                 did you mean `max_line_length`?",
+                "unknown key `tabs_alowed` in [override] at line 13 column 1, \
+                 did you mean `tabs_allowed`?",
+                "unknown key `completely_different` in [override] at line 14 column 1",
+            ]
+        );
+        let err = parse_config("ignore = []\nendings = []\n[rule]\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unknown key `rule` at line 3 column 2, did you mean `rules`?"
+        );
+        let c = r#"# tabs_alowed is not a key
+ignore = ["tabs_alowed"]
+endings = []
+[rules]
+max_line_length = 80  # tabs_alowed
+ tabs_alowed = true
+"#;
+        let err = parse_config(c).unwrap_err();
+        assert!(
+            err.to_string().contains("in [rules] at line 6 column 2"),
+            "{}",
+            err
+        );
+        let err =
+            parse_config("ignore = []\nendings = []\nrules.tabs_alowed = true\n").unwrap_err();
+        assert!(
+            err.to_string().contains("in [rules] at line 3 column 7"),
+            "{}",
+            err
+        );
+        let err = parse_config("ignore = []\n[run]\nthreads = \"many\"\n").unwrap_err();
+        assert!(err.to_string().contains("line 3 column 11"), "{}", err);
+        let err = parse_config("ignore = []\n").unwrap_err();
+        assert_eq!(err.to_string(), "missing key `endings`");
+    }
+    #[test]
+    fn test_known_keys_match_the_config() {
+        let keys = |v: serde_json::Value| -> Vec<String> {
+            let mut keys: Vec<String> = v.as_object().unwrap().keys().cloned().collect();
+            keys.sort();
+            keys
+        };
+        let sorted = |k: &[&str]| -> Vec<String> {
+            let mut k: Vec<String> = k.iter().map(|s| s.to_string()).collect();
+            k.sort();
+            k
+        };
+        let full_override = Override {
+            paths: vec![s("a")],
+            endings: vec![s(".c")],
+            enable: vec![Rule::Tabs],
+            disable: vec![Rule::Tabs],
+            max_line_length: Some(1),
+            indent_width: Some(2),
+            tabs_allowed: Some(true),
+            line_ending: Some(LineEnding::LF),
+            bom: Some(Bom::Require),
+        };
+        let mut cfg = default_cfg();
+        cfg.tab_widths.insert(s(".c"), 2);
+        cfg.overrides.push(full_override.clone());
+        assert_eq!(keys(serde_json::to_value(&cfg).unwrap()), sorted(CFG_KEYS));
+        let rules = serde_json::to_value(RulesCfg::default()).unwrap();
+        assert_eq!(keys(rules), sorted(RULES_KEYS));
+        let run = serde_json::to_value(RunCfg::default()).unwrap();
+        assert_eq!(keys(run), sorted(RUN_KEYS));
+        let o = serde_json::to_value(full_override).unwrap();
+        assert_eq!(keys(o), sorted(OVERRIDE_KEYS));
+    }
+    #[test]
+    fn test_missing_config_is_an_error() {
+        let dir = std::env::temp_dir().join(format!("enforcer_nocfg_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join(".git")).unwrap();
+        let tree = CfgTree::new(None, false, |cfg| cfg).unwrap();
+        tree.cfg_in(&dir);
+        assert!(tree.check().is_err());
+        let tree = CfgTree::new(None, true, |cfg| cfg).unwrap();
+        assert_eq!(*tree.cfg_in(&dir), default_cfg());
+        assert!(tree.check().is_ok());
+        assert!(CfgTree::new(Some(&dir.join("missing")), false, |cfg| cfg).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+    #[test]
+    fn test_invalid_overrides_are_rejected() {
+        let unknown_rule = r#"
+            ignore = []
+            endings = [".c"]
+            [[override]]
+            paths = ["a/**"]
+            disable = ["NO_SUCH_RULE"]
+        "#;
+        assert!(parse_config(unknown_rule).is_err());
+        let nothing_selected = r#"
+            ignore = []
+            endings = [".c"]
+            [[override]]
+            tabs_allowed = true
+        "#;
+        assert!(parse_config(nothing_selected).is_err());
+    }
+    #[test]
     #[should_panic]
     fn test_load_broken_config() {
         let c = include_str!("../samples/.enforcer_broken");
@@ -176,4 +1100,42 @@ mod tests {
         assert_eq!("**/fat32/**".to_string(), suggestion("fat32"));
         assert_eq!("**/gtest.h".to_string(), suggestion("gtest.h"));
     }
+
+    #[test]
+    fn test_migrate() {
+        let old = r#"# ignored stuff
+ignore = [
+    ".git", # the repo
+    "**/.bake",
+    'build_*',
+]
+endings = ["*.o"]
+
+[rules]
+allow_tabs = true
+"#;
+        let migrated = migrate(old).unwrap().unwrap();
+        assert_eq!(
+            r#"# ignored stuff
+ignore = [
+    "**/.git", # the repo
+    "**/.bake",
+    "**/build_*/**",
+]
+endings = ["*.o"]
+
+[rules]
+allow_tabs = true
+"#,
+            migrated
+        );
+        assert_eq!(None, migrate(&migrated).unwrap());
+        assert!(migrate("ignore = [").is_err());
+        assert_eq!(
+            "--- .enforcer\n+++ .enforcer\n\
+             @@ line 3 @@\n-    \".git\", # the repo\n+    \"**/.git\", # the repo\n\
+             @@ line 5 @@\n-    'build_*',\n+    \"**/build_*/**\",\n",
+            migration_diff(Path::new(".enforcer"), old, &migrated)
+        );
+    }
 }
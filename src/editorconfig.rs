@@ -0,0 +1,451 @@
+use crate::{
+    clean::{Bom, LineEnding},
+    search,
+};
+use regex::Regex;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+/// Name of the EditorConfig files (see https://editorconfig.org).
+pub const EDITORCONFIG_FILE: &str = ".editorconfig";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IndentStyle {
+    Tab,
+    Space,
+}
+
+/// The EditorConfig properties enforcer knows, `None` if they are not set
+/// (or unset) for a file.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Properties {
+    pub indent_style: Option<IndentStyle>,
+    pub tab_width: Option<usize>,
+    /// `tab` is the tab width
+    pub indent_size: Option<usize>,
+    pub end_of_line: Option<LineEnding>,
+    pub trim_trailing_whitespace: Option<bool>,
+    pub insert_final_newline: Option<bool>,
+    /// `utf-8` forbids the byte order mark, `utf-8-bom` requires it
+    pub charset: Option<Bom>,
+    /// `Some(None)` if the line length is not limited (`off`)
+    pub max_line_length: Option<Option<usize>>,
+}
+
+impl Properties {
+    // interpret the raw values of all matching sections
+    fn from_values(values: &HashMap<String, String>) -> Properties {
+        let get = |key: &str| values.get(key).map(String::as_str);
+        let number = |key: &str| get(key).and_then(|v| v.parse().ok()).filter(|n| *n > 0);
+        let flag = |key: &str| match get(key) {
+            Some("true") => Some(true),
+            Some("false") => Some(false),
+            _ => None,
+        };
+        // tab_width defaults to indent_size
+        let tab_width = number("tab_width").or_else(|| number("indent_size"));
+        Properties {
+            indent_style: match get("indent_style") {
+                Some("tab") => Some(IndentStyle::Tab),
+                Some("space") => Some(IndentStyle::Space),
+                _ => None,
+            },
+            tab_width,
+            indent_size: match get("indent_size") {
+                Some("tab") => tab_width,
+                _ => number("indent_size"),
+            },
+            end_of_line: match get("end_of_line") {
+                Some("lf") => Some(LineEnding::LF),
+                Some("crlf") => Some(LineEnding::CRLF),
+                _ => None,
+            },
+            trim_trailing_whitespace: flag("trim_trailing_whitespace"),
+            insert_final_newline: flag("insert_final_newline"),
+            charset: match get("charset") {
+                Some("utf-8") => Some(Bom::Forbid),
+                Some("utf-8-bom") => Some(Bom::Require),
+                _ => None,
+            },
+            max_line_length: match get("max_line_length") {
+                Some("off") => Some(None),
+                _ => number("max_line_length").map(Some),
+            },
+        }
+    }
+}
+
+// a section glob as a regex, every `{n..m}` range in it is a capture group
+// whose number is checked against the bounds
+#[derive(Debug)]
+struct SectionPattern {
+    regex: Regex,
+    ranges: Vec<(i64, i64)>,
+}
+
+impl SectionPattern {
+    fn new(glob: &str) -> Option<SectionPattern> {
+        let mut ranges = Vec::new();
+        let regex = Regex::new(&section_regex(glob, &mut ranges)).ok()?;
+        Some(SectionPattern { regex, ranges })
+    }
+
+    fn is_match(&self, path: &str) -> bool {
+        let captures = match self.regex.captures(path) {
+            Some(captures) => captures,
+            None => return false,
+        };
+        self.ranges
+            .iter()
+            .enumerate()
+            .all(|(i, (from, to))| match captures.get(i + 1) {
+                Some(n) => n
+                    .as_str()
+                    .parse::<i64>()
+                    .is_ok_and(|n| *from <= n && n <= *to),
+                None => true,
+            })
+    }
+}
+
+#[derive(Debug)]
+struct Section {
+    pattern: Option<SectionPattern>,
+    values: Vec<(String, String)>,
+}
+
+/// A parsed `.editorconfig` file.
+#[derive(Debug)]
+pub struct EditorConfig {
+    root: bool,
+    sections: Vec<Section>,
+}
+
+impl EditorConfig {
+    pub fn parse(content: &str) -> EditorConfig {
+        let mut root = false;
+        let mut sections: Vec<Section> = Vec::new();
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                let glob = &line[1..line.len() - 1];
+                sections.push(Section {
+                    pattern: SectionPattern::new(glob),
+                    values: Vec::new(),
+                });
+                continue;
+            }
+            if let Some((key, value)) = line.split_once('=') {
+                let key = key.trim().to_lowercase();
+                let value = value.trim().to_lowercase();
+                match sections.last_mut() {
+                    Some(section) => section.values.push((key, value)),
+                    None if key == "root" => root = value == "true",
+                    None => {}
+                }
+            }
+        }
+        EditorConfig { root, sections }
+    }
+
+    // collect the values of all sections matching a path relative to the
+    // directory of the file, later ones win
+    fn apply(&self, rel: &str, values: &mut HashMap<String, String>) {
+        let matching = self
+            .sections
+            .iter()
+            .filter(|s| s.pattern.as_ref().is_some_and(|p| p.is_match(rel)));
+        for section in matching {
+            for (key, value) in &section.values {
+                values.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+// a section glob matches relative to the directory of the file if it
+// contains a slash, otherwise it matches file names in all directories
+fn section_regex(glob: &str, ranges: &mut Vec<(i64, i64)>) -> String {
+    if glob.contains('/') {
+        format!("^{}$", glob_regex(glob.trim_start_matches('/'), ranges))
+    } else {
+        format!("^(?:.*/)?{}$", glob_regex(glob, ranges))
+    }
+}
+
+// translate an EditorConfig glob: `*`, `**`, `?`, `[seq]`, `[!seq]`,
+// `{a,b}` and `{1..3}` (its bounds are added to `ranges`)
+fn glob_regex(glob: &str, ranges: &mut Vec<(i64, i64)>) -> String {
+    let chars: Vec<char> = glob.chars().collect();
+    let mut res = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                res.push_str(".*");
+                i += 1;
+            }
+            '*' => res.push_str("[^/]*"),
+            '?' => res.push_str("[^/]"),
+            '\\' if i + 1 < chars.len() => {
+                i += 1;
+                res.push_str(&regex::escape(&chars[i].to_string()));
+            }
+            '[' => match chars[i..].iter().position(|c| *c == ']') {
+                Some(len) if !chars[i..i + len].contains(&'/') => {
+                    let seq: String = chars[i + 1..i + len].iter().collect();
+                    let (negated, seq) = match seq.strip_prefix('!') {
+                        Some(rest) => (true, rest.to_string()),
+                        None => (false, seq),
+                    };
+                    let seq = seq.replace('\\', "\\\\").replace('[', "\\[");
+                    res.push_str(&format!("[{}{}]", if negated { "^" } else { "" }, seq));
+                    i += len;
+                }
+                _ => res.push_str("\\["),
+            },
+            '{' => match closing_brace(&chars[i..]) {
+                Some(len) => {
+                    let inner: String = chars[i + 1..i + len].iter().collect();
+                    res.push_str(&braces_regex(&inner, ranges));
+                    i += len;
+                }
+                None => res.push_str("\\{"),
+            },
+            c => res.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+    res
+}
+
+// position of the brace closing the one at the start
+fn closing_brace(chars: &[char]) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in chars.iter().enumerate() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn braces_regex(inner: &str, ranges: &mut Vec<(i64, i64)>) -> String {
+    if let Some((from, to)) = inner.split_once("..") {
+        if let (Ok(from), Ok(to)) = (from.parse::<i64>(), to.parse::<i64>()) {
+            ranges.push((from.min(to), from.max(to)));
+            return String::from("([+-]?[0-9]+)");
+        }
+    }
+    let mut alternatives = Vec::new();
+    let mut depth = 0;
+    let mut current = String::new();
+    for c in inner.chars() {
+        match c {
+            ',' if depth == 0 => {
+                alternatives.push(std::mem::take(&mut current));
+                continue;
+            }
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            _ => {}
+        }
+        current.push(c);
+    }
+    alternatives.push(current);
+    if alternatives.len() == 1 {
+        // a single word in braces is taken literally
+        return format!("\\{{{}\\}}", glob_regex(inner, ranges));
+    }
+    let alternatives: Vec<String> = alternatives.iter().map(|a| glob_regex(a, ranges)).collect();
+    format!("(?:{})", alternatives.join("|"))
+}
+
+// parsed `.editorconfig` files with the directory they are in
+type Chain = Vec<(PathBuf, Arc<EditorConfig>)>;
+
+/// Finds the `.editorconfig` files for every file (from the file up to the
+/// first one with `root = true`).
+#[derive(Default)]
+pub struct EditorConfigs {
+    /// the parsed files that apply to a directory, innermost first
+    dirs: Mutex<HashMap<PathBuf, Chain>>,
+}
+
+impl EditorConfigs {
+    pub fn properties_for(&self, path: &Path) -> Properties {
+        let file = match search::resolved(path) {
+            Some(file) => file,
+            None => return Properties::default(),
+        };
+        let dir = file.parent().unwrap_or_else(|| Path::new("/"));
+        let configs = self
+            .dirs
+            .lock()
+            .expect("editorconfig cache")
+            .entry(dir.to_path_buf())
+            .or_insert_with(|| editorconfigs(dir))
+            .clone();
+        let mut values = HashMap::new();
+        for (config_dir, config) in configs.iter().rev() {
+            if let Ok(rel) = file.strip_prefix(config_dir) {
+                config.apply(&rel.to_string_lossy(), &mut values);
+            }
+        }
+        Properties::from_values(&values)
+    }
+}
+
+fn editorconfigs(dir: &Path) -> Chain {
+    let mut res = Vec::new();
+    for d in dir.ancestors() {
+        if let Ok(content) = fs::read_to_string(d.join(EDITORCONFIG_FILE)) {
+            let config = EditorConfig::parse(&content);
+            let root = config.root;
+            res.push((d.to_path_buf(), Arc::new(config)));
+            if root {
+                break;
+            }
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EditorConfig, EditorConfigs, IndentStyle, Properties, SectionPattern};
+    use crate::clean::{Bom, LineEnding};
+    use std::{collections::HashMap, fs};
+
+    fn matches(glob: &str, path: &str) -> bool {
+        SectionPattern::new(glob).unwrap().is_match(path)
+    }
+
+    fn properties(content: &str, rel: &str) -> Properties {
+        let mut values = HashMap::new();
+        EditorConfig::parse(content).apply(rel, &mut values);
+        Properties::from_values(&values)
+    }
+
+    #[test]
+    fn test_section_globs() {
+        assert!(matches("*", "a.c"));
+        assert!(matches("*", "src/a.c"));
+        assert!(matches("*.c", "src/deep/a.c"));
+        assert!(!matches("*.c", "a.cpp"));
+        assert!(matches("*.{c,h}", "src/a.h"));
+        assert!(!matches("*.{c,h}", "src/a.cpp"));
+        assert!(matches("{Makefile,*.mk}", "sub/Makefile"));
+        assert!(matches("/src/*.c", "src/a.c"));
+        assert!(!matches("src/*.c", "src/deep/a.c"));
+        assert!(!matches("src/*.c", "lib/src/a.c"));
+        assert!(matches("src/**.c", "src/deep/a.c"));
+        assert!(matches("lib/**/*.c", "lib/a/b/c.c"));
+        assert!(matches("a?.c", "ab.c"));
+        assert!(!matches("a?.c", "a/.c"));
+        assert!(matches("[ab].c", "b.c"));
+        assert!(!matches("[!ab].c", "b.c"));
+        assert!(matches("file{1..3}.c", "file2.c"));
+        assert!(!matches("file{1..3}.c", "file4.c"));
+        assert!(matches("file{3..1}.c", "file1.c"));
+        assert!(matches("v{-5..5}/{1..2}.c", "v-3/2.c"));
+        assert!(!matches("v{-5..5}/{1..2}.c", "v-3/3.c"));
+        assert!(matches("{*.h,file{1..3}.c}", "a.h"));
+        assert!(matches("{*.h,file{1..3}.c}", "file3.c"));
+        assert!(!matches("{*.h,file{1..3}.c}", "file0.c"));
+        assert!(matches("{single}.c", "{single}.c"));
+        assert!(matches("a\\*.c", "a*.c"));
+        assert!(!matches("a\\*.c", "ab.c"));
+    }
+
+    #[test]
+    fn test_huge_brace_range() {
+        assert!(matches("{1..1000000}", "999999"));
+        assert!(!matches("{1..1000000}", "1000001"));
+        assert!(!matches("{1..1000000}", "0"));
+    }
+
+    #[test]
+    fn test_properties() {
+        let content = r#"
+            # top-most EditorConfig file
+            root = true
+
+            [*]
+            end_of_line = lf
+            trim_trailing_whitespace = true
+            insert_final_newline = true
+            charset = utf-8
+            indent_style = space
+            indent_size = 4
+
+            [*.{c,h}]
+            max_line_length = 100
+
+            [Makefile]
+            indent_style = tab
+            indent_size = tab
+            tab_width = 8
+            max_line_length = off
+
+            [*.bat]
+            charset = utf-8-bom
+            end_of_line = CRLF
+            trim_trailing_whitespace = unset
+        "#;
+        assert!(EditorConfig::parse(content).root);
+        let c = properties(content, "src/a.c");
+        assert_eq!(c.indent_style, Some(IndentStyle::Space));
+        assert_eq!(c.tab_width, Some(4));
+        assert_eq!(c.indent_size, Some(4));
+        assert_eq!(c.max_line_length, Some(Some(100)));
+        assert_eq!(c.end_of_line, Some(LineEnding::LF));
+        assert_eq!(c.trim_trailing_whitespace, Some(true));
+        assert_eq!(c.insert_final_newline, Some(true));
+        assert_eq!(c.charset, Some(Bom::Forbid));
+        let make = properties(content, "Makefile");
+        assert_eq!(make.indent_style, Some(IndentStyle::Tab));
+        assert_eq!(make.tab_width, Some(8));
+        assert_eq!(make.indent_size, Some(8));
+        assert_eq!(make.max_line_length, Some(None));
+        let bat = properties(content, "run.bat");
+        assert_eq!(bat.end_of_line, Some(LineEnding::CRLF));
+        assert_eq!(bat.trim_trailing_whitespace, None);
+        assert_eq!(bat.charset, Some(Bom::Require));
+        assert_eq!(bat.max_line_length, None);
+    }
+
+    #[test]
+    fn test_nested_editorconfigs() {
+        let dir = std::env::temp_dir().join(format!("enforcer_ec_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(
+            dir.join(".editorconfig"),
+            "root = true\n[*]\nindent_size = 2\n[sub/*.c]\nmax_line_length = 80\n",
+        )
+        .unwrap();
+        fs::write(dir.join("sub/.editorconfig"), "[*.c]\nindent_size = 3\n").unwrap();
+        let configs = EditorConfigs::default();
+        let top = configs.properties_for(&dir.join("a.c"));
+        assert_eq!(top.tab_width, Some(2));
+        assert_eq!(top.max_line_length, None);
+        let sub = configs.properties_for(&dir.join("sub/b.c"));
+        assert_eq!(sub.tab_width, Some(3));
+        assert_eq!(sub.max_line_length, Some(Some(80)));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
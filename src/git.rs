@@ -0,0 +1,306 @@
+use anyhow::{anyhow, Error};
+use std::{
+    collections::BTreeMap,
+    ops::Range,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Line ranges (1-based, end exclusive) that were added or modified per file.
+pub type ChangedLines = BTreeMap<PathBuf, Vec<Range<usize>>>;
+
+fn git(dir: &Path, args: &[&str]) -> Result<Vec<u8>, Error> {
+    let output = Command::new("git").current_dir(dir).args(args).output()?;
+    if output.status.success() {
+        Ok(output.stdout)
+    } else {
+        Err(anyhow!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+/// All files below `dir` that are staged for the next commit (added, copied,
+/// modified or renamed), prefixed with `dir` like the paths found by walking
+/// the directory.
+pub fn staged_files(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let out = git(
+        dir,
+        &[
+            "diff",
+            "--cached",
+            "--name-only",
+            "--relative",
+            "--diff-filter=ACMR",
+            "-z",
+        ],
+    )
+    .map_err(|e| anyhow!("could not get staged files in {:?}: {}", dir, e))?;
+    Ok(out
+        .split(|b| *b == 0)
+        .filter(|p| !p.is_empty())
+        .map(|p| dir.join(String::from_utf8_lossy(p).as_ref()))
+        .collect())
+}
+
+/// The directory git has to be run in for a path (the path itself for
+/// directories, the parent directory for files).
+pub fn dir_of(path: &Path) -> &Path {
+    if path.is_dir() {
+        return path;
+    }
+    match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    }
+}
+
+/// The content of a file as it is staged in the git index.
+pub fn staged_content(path: &Path) -> Result<Vec<u8>, Error> {
+    let dir = dir_of(path);
+    let name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("not a file: {:?}", path))?;
+    git(dir, &["show", &format!(":./{}", name.to_string_lossy())])
+}
+
+// "@@ -a,b +c,d @@" => c..c+d (d defaults to 1)
+fn parse_hunk_header(line: &str) -> Option<Range<usize>> {
+    let added = line.split(' ').find(|s| s.starts_with('+'))?;
+    let mut parts = added[1..].splitn(2, ',');
+    let start: usize = parts.next()?.parse().ok()?;
+    let count: usize = match parts.next() {
+        Some(c) => c.parse().ok()?,
+        None => 1,
+    };
+    Some(start..start + count)
+}
+
+// git quotes paths with unusual characters like C strings, non-ASCII bytes
+// are octal escapes: "b/caf\303\251.c"
+fn unquote(name: &str) -> String {
+    let inner = match name.strip_prefix('"').and_then(|n| n.strip_suffix('"')) {
+        Some(inner) => inner,
+        None => return name.to_string(),
+    };
+    let mut bytes = Vec::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        let escaped = match c {
+            '\\' => chars.next(),
+            c => {
+                bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                continue;
+            }
+        };
+        match escaped {
+            Some('a') => bytes.push(0x07),
+            Some('b') => bytes.push(0x08),
+            Some('t') => bytes.push(b'\t'),
+            Some('n') => bytes.push(b'\n'),
+            Some('v') => bytes.push(0x0b),
+            Some('f') => bytes.push(0x0c),
+            Some('r') => bytes.push(b'\r'),
+            Some(d @ '0'..='7') => {
+                let digits: String = std::iter::once(d).chain(chars.by_ref().take(2)).collect();
+                bytes.push(u8::from_str_radix(&digits, 8).unwrap_or(b'?'));
+            }
+            // `\"` and `\\`
+            Some(c) => bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+            None => {}
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+// path of a "+++ b/path" line
+fn parse_new_file(line: &str) -> Option<PathBuf> {
+    let name = unquote(line.trim_start_matches("+++ ").trim_end());
+    name.strip_prefix("b/").map(PathBuf::from)
+}
+
+/// Extract the added/modified line ranges from a unified diff (as produced
+/// by `git diff -U0`).
+pub fn parse_unified_diff(diff: &str) -> ChangedLines {
+    let mut res = ChangedLines::new();
+    let mut current: Option<PathBuf> = None;
+    for line in diff.lines() {
+        if line.starts_with("+++ ") {
+            current = parse_new_file(line);
+        } else if line.starts_with("@@ ") {
+            if let (Some(file), Some(range)) = (&current, parse_hunk_header(line)) {
+                let ranges = res.entry(file.clone()).or_default();
+                if !range.is_empty() {
+                    ranges.push(range);
+                }
+            }
+        }
+    }
+    res
+}
+
+// the commit a user given ref points to, refs that look like options are
+// rejected so they cannot be taken for one by git
+fn resolve_commit(dir: &Path, base: &str) -> Result<String, Error> {
+    if base.starts_with('-') {
+        return Err(anyhow!("invalid git ref: {}", base));
+    }
+    let commit = format!("{}^{{commit}}", base);
+    let out = git(dir, &["rev-parse", "--verify", "--quiet", &commit])
+        .map_err(|_| anyhow!("unknown git ref: {}", base))?;
+    Ok(String::from_utf8_lossy(&out).trim().to_string())
+}
+
+/// The lines below `dir` that changed compared to `base` (in the working
+/// tree, or in the index if `cached` is set). Paths are prefixed with `dir`.
+pub fn changed_lines(dir: &Path, base: &str, cached: bool) -> Result<ChangedLines, Error> {
+    let commit = resolve_commit(dir, base)?;
+    let mut args = vec![
+        "diff",
+        "-U0",
+        "--no-color",
+        "--no-ext-diff",
+        "--relative",
+        "--src-prefix=a/",
+        "--dst-prefix=b/",
+    ];
+    if cached {
+        args.push("--cached");
+    }
+    args.push(&commit);
+    args.push("--");
+    let out = git(dir, &args).map_err(|e| anyhow!("could not diff against {}: {}", base, e))?;
+    Ok(parse_unified_diff(&String::from_utf8_lossy(&out))
+        .into_iter()
+        .map(|(p, ranges)| (dir.join(p), ranges))
+        .collect())
+}
+
+/// Whether a line is part of the given changed ranges.
+pub fn is_changed(line: usize, ranges: &[Range<usize>]) -> bool {
+    ranges.iter().any(|r| r.contains(&line))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        changed_lines, git, is_changed, parse_unified_diff, staged_content, staged_files, unquote,
+    };
+    use std::{fs, path::PathBuf};
+
+    #[test]
+    fn test_parse_unified_diff() {
+        let diff = "\
+diff --git a/src/a.c b/src/a.c
+index 1111111..2222222 100644
+--- a/src/a.c
++++ b/src/a.c
+@@ -3 +3 @@ int main()
+-old
++new
+@@ -10,0 +11,3 @@
++x
++y
++z
+@@ -20,2 +22,0 @@
+-gone
+-gone
+diff --git a/b.h b/b.h
+new file mode 100644
+--- /dev/null
++++ b/b.h
+@@ -0,0 +1,2 @@
++a
++b
+diff --git a/c.h b/c.h
+deleted file mode 100644
+--- a/c.h
++++ /dev/null
+@@ -1 +0,0 @@
+-a
+";
+        let changed = parse_unified_diff(diff);
+        assert_eq!(changed.len(), 2);
+        let a = &changed[&PathBuf::from("src/a.c")];
+        assert_eq!(a, &vec![3..4, 11..14]);
+        assert!(is_changed(3, a));
+        assert!(!is_changed(4, a));
+        assert!(is_changed(13, a));
+        assert!(!is_changed(14, a));
+        assert_eq!(changed[&PathBuf::from("b.h")], vec![1..3]);
+    }
+
+    #[test]
+    fn test_quoted_paths() {
+        assert_eq!(unquote("b/a.c"), "b/a.c");
+        assert_eq!(unquote(r#""b/caf\303\251.c""#), "b/café.c");
+        assert_eq!(unquote(r#""b/a \"b\"\\\t.c""#), "b/a \"b\"\\\t.c");
+        let diff = "+++ \"b/caf\\303\\251.c\"\n@@ -1 +1 @@\n-a\n+a \n";
+        assert_eq!(
+            parse_unified_diff(diff)[&PathBuf::from("café.c")],
+            vec![1..2]
+        );
+    }
+
+    #[test]
+    fn test_staged_content() {
+        let dir = std::env::temp_dir().join(format!("enforcer_git_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("src")).unwrap();
+        git(&dir, &["init", "-q"]).unwrap();
+        fs::write(dir.join("src/a.c"), "staged\n").unwrap();
+        fs::write(dir.join("src/b.c"), "not staged\n").unwrap();
+        git(&dir, &["add", "src/a.c"]).unwrap();
+        fs::write(dir.join("src/a.c"), "working copy\n").unwrap();
+
+        let files = staged_files(&dir).unwrap();
+        assert_eq!(files, vec![dir.join("src/a.c")]);
+        assert_eq!(staged_content(&files[0]).unwrap(), b"staged\n");
+        assert_eq!(
+            staged_files(&dir.join("src")).unwrap(),
+            vec![dir.join("src").join("a.c")]
+        );
+        assert!(staged_content(&dir.join("src/b.c")).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_changed_lines() {
+        let dir = std::env::temp_dir().join(format!("enforcer_diff_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        git(&dir, &["init", "-q"]).unwrap();
+        fs::write(dir.join("a.c"), "1\n2\n3\n").unwrap();
+        fs::write(dir.join("café.c"), "1\n").unwrap();
+        git(&dir, &["add", "a.c", "café.c"]).unwrap();
+        git(
+            &dir,
+            &[
+                "-c",
+                "user.name=t",
+                "-c",
+                "user.email=t@t",
+                "commit",
+                "-q",
+                "-m",
+                "init",
+            ],
+        )
+        .unwrap();
+        fs::write(dir.join("a.c"), "1\ntwo\n3\n4\n").unwrap();
+        let changed = changed_lines(&dir, "HEAD", false).unwrap();
+        assert_eq!(changed[&dir.join("a.c")], vec![2..3, 4..5]);
+        fs::write(dir.join("café.c"), "1 \n").unwrap();
+        let changed = changed_lines(&dir, "HEAD", false).unwrap();
+        assert_eq!(changed[&dir.join("café.c")], vec![1..2]);
+        assert!(changed_lines(&dir, "HEAD", true).unwrap().is_empty());
+        let err = changed_lines(&dir, "--output=x", false).unwrap_err();
+        assert_eq!(err.to_string(), "invalid git ref: --output=x");
+        assert!(!dir.join("x").exists());
+        let err = changed_lines(&dir, "nope", false).unwrap_err();
+        assert_eq!(err.to_string(), "unknown git ref: nope");
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
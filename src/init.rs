@@ -0,0 +1,271 @@
+use crate::{
+    clean::{self, LineEnding},
+    config::DEFAULT_TAB_WIDTH,
+    search::{self, Selection},
+};
+use glob::Pattern;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+/// Names of directories that are generated or vendored, they are proposed
+/// as `ignore` globs when found.
+const GENERATED_DIRS: &[&str] = &[".git", ".repo", ".bake", "target", "build*", "node_modules"];
+/// Line lengths that are proposed as `max_line_length`.
+const COMMON_LENGTHS: &[usize] = &[80, 100, 120];
+/// Share of the lines (in percent) that have to fit the proposed length.
+const LENGTH_COVERAGE: usize = 99;
+
+/// What `enforcer init` found in a directory tree.
+#[derive(Debug, Default, PartialEq)]
+pub struct Survey {
+    /// the `ignore` globs of the generated directories found
+    pub ignore: BTreeSet<String>,
+    /// number of text files per ending
+    pub endings: BTreeMap<String, usize>,
+    pub tab_indented: usize,
+    pub space_indented: usize,
+    pub lf_lines: usize,
+    pub crlf_lines: usize,
+    /// number of lines per width (tabs expanded)
+    pub widths: BTreeMap<usize, usize>,
+}
+
+// walks everything except the generated directories, which are remembered
+struct Scan {
+    root: PathBuf,
+    found: Mutex<BTreeSet<String>>,
+}
+
+impl Selection for Scan {
+    fn is_ignored(&self, path: &Path) -> bool {
+        let name = match path.file_name().and_then(|n| n.to_str()) {
+            Some(name) if path != self.root && path.is_dir() => name,
+            _ => return false,
+        };
+        let generated = GENERATED_DIRS
+            .iter()
+            .any(|d| Pattern::new(d).is_ok_and(|p| p.matches(name)));
+        if generated {
+            let mut found = self.found.lock().expect("scan lock");
+            found.insert(format!("**/{}", name));
+        }
+        generated
+    }
+    fn has_ending(&self, _file: &Path) -> bool {
+        true
+    }
+}
+
+impl Survey {
+    /// Look at all files below `dir` (ignore files are not honored).
+    pub fn of(dir: &Path) -> Survey {
+        let scan = Arc::new(Scan {
+            root: dir.to_path_buf(),
+            found: Mutex::new(BTreeSet::new()),
+        });
+        let mut survey = Survey::default();
+        for file in search::find_matches(dir, scan.clone(), false) {
+            if let Ok(content) = fs::read(&file) {
+                survey.add(&file, &content);
+            }
+        }
+        survey.ignore = scan.found.lock().expect("scan lock").clone();
+        survey
+    }
+
+    // binary files and files without an ending are skipped
+    fn add(&mut self, file: &Path, content: &[u8]) {
+        let ending = match file.extension().and_then(|e| e.to_str()) {
+            Some(e) => format!(".{}", e),
+            None => return,
+        };
+        let text = match std::str::from_utf8(content) {
+            Ok(text) if !text.contains('\0') => text,
+            _ => return,
+        };
+        *self.endings.entry(ending).or_insert(0) += 1;
+        for line in text.split_inclusive('\n') {
+            if line.ends_with("\r\n") {
+                self.crlf_lines += 1;
+            } else if line.ends_with('\n') {
+                self.lf_lines += 1;
+            }
+            let line = line.trim_end_matches(&['\r', '\n'][..]);
+            match line.chars().next() {
+                Some('\t') => self.tab_indented += 1,
+                Some(' ') => self.space_indented += 1,
+                _ => (),
+            }
+            let width = clean::expanded_width(line, DEFAULT_TAB_WIDTH);
+            *self.widths.entry(width).or_insert(0) += 1;
+        }
+    }
+
+    pub fn allow_tabs(&self) -> bool {
+        self.tab_indented > self.space_indented
+    }
+
+    pub fn line_ending(&self) -> LineEnding {
+        if self.crlf_lines > self.lf_lines {
+            LineEnding::CRLF
+        } else {
+            LineEnding::LF
+        }
+    }
+
+    /// The smallest common line length that fits almost all lines, `None`
+    /// if the lines are longer than that.
+    pub fn max_line_length(&self) -> Option<usize> {
+        let lines: usize = self.widths.values().sum();
+        let longest = self.widths.iter().rev().try_fold(0, |outside, (width, n)| {
+            if (outside + n) * 100 > lines * (100 - LENGTH_COVERAGE) {
+                Err(*width)
+            } else {
+                Ok(outside + n)
+            }
+        });
+        match longest {
+            Err(width) => COMMON_LENGTHS.iter().copied().find(|l| *l >= width),
+            Ok(_) => None,
+        }
+    }
+
+    /// A commented `.enforcer` file with the proposed settings.
+    pub fn config(&self) -> String {
+        let percent = |part: usize, all: usize| part * 100 / all.max(1);
+        let list = |items: Vec<&String>| {
+            items
+                .iter()
+                .map(|i| format!("{:?}", i))
+                .collect::<Vec<String>>()
+                .join(", ")
+        };
+        let mut found: Vec<(&String, &usize)> = self.endings.iter().collect();
+        found.sort_by(|a, b| b.1.cmp(a.1));
+        let found = found
+            .iter()
+            .map(|(e, n)| format!("{} {}", e, n))
+            .collect::<Vec<String>>()
+            .join(", ");
+        let indented = self.tab_indented + self.space_indented;
+        let (style, indent_count) = if self.allow_tabs() {
+            ("tabs", self.tab_indented)
+        } else {
+            ("spaces", self.space_indented)
+        };
+        let (ending, ending_count) = if self.line_ending() == LineEnding::CRLF {
+            ("crlf", self.crlf_lines)
+        } else {
+            ("lf", self.lf_lines)
+        };
+        let mut cfg = String::from("# generated by `enforcer init`, adjust it to your needs\n\n");
+        cfg += "# generated and vendored directories that are not checked\n";
+        cfg += &format!("ignore = [{}]\n", list(self.ignore.iter().collect()));
+        cfg += &format!("# file endings that are checked (found: {})\n", found);
+        cfg += &format!("endings = [{}]\n\n", list(self.endings.keys().collect()));
+        cfg += "[rules]\n";
+        if indented == 0 {
+            cfg += "# no indented lines found\n# allow_tabs = false\n";
+        } else {
+            cfg += &format!(
+                "# {}% of the indented lines use {}\nallow_tabs = {}\n",
+                percent(indent_count, indented),
+                style,
+                self.allow_tabs()
+            );
+        }
+        cfg += &format!(
+            "# {}% of the lines end with {}\nline_ending = \"{}\"\n",
+            percent(ending_count, self.lf_lines + self.crlf_lines),
+            ending.to_uppercase(),
+            ending
+        );
+        match self.max_line_length() {
+            Some(length) => {
+                cfg += &format!(
+                    "# at least {}% of the lines fit\nmax_line_length = {}\n",
+                    LENGTH_COVERAGE, length
+                )
+            }
+            None => cfg += "# the lines are too long for a common limit\n# max_line_length = 120\n",
+        }
+        cfg
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Survey;
+    use crate::{clean::LineEnding, config::parse_config};
+    use std::{fs, path::Path};
+
+    #[test]
+    fn test_conventions() {
+        let mut survey = Survey::default();
+        survey.add(Path::new("a.c"), b"int a;\r\n\tint b;\r\n\tint c;\r\n");
+        survey.add(Path::new("b.h"), b"  int d;\n");
+        survey.add(Path::new("logo.png"), b"\x89PNG\r\n\x1a\n\0\0");
+        survey.add(Path::new("Makefile"), b"all:\n");
+        assert!(survey.allow_tabs());
+        assert_eq!(LineEnding::CRLF, survey.line_ending());
+        assert_eq!(vec![".c", ".h"], survey.endings.keys().collect::<Vec<_>>());
+        assert_eq!(Some(80), survey.max_line_length());
+
+        let long = "x".repeat(110) + "\n";
+        survey.add(Path::new("c.h"), long.repeat(10).as_bytes());
+        assert_eq!(Some(120), survey.max_line_length());
+        survey.add(Path::new("d.h"), "y".repeat(200).as_bytes());
+        assert_eq!(None, survey.max_line_length());
+    }
+
+    #[test]
+    fn test_no_indented_lines() {
+        let mut survey = Survey::default();
+        survey.add(Path::new("a.md"), b"# title\ntext\n");
+        let cfg = survey.config();
+        assert!(cfg.contains("# no indented lines found\n# allow_tabs = false\n"));
+        assert!(!cfg.contains("% of the indented lines"));
+        assert!(!parse_config(&cfg).unwrap().rules.allow_tabs);
+    }
+
+    #[test]
+    fn test_survey_of_tree() {
+        let dir = std::env::temp_dir().join(format!("enforcer_init_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for d in &[".git", "build_x86", "src/node_modules/lib", "target"] {
+            fs::create_dir_all(dir.join(d)).unwrap();
+        }
+        fs::write(dir.join("src/main.rs"), "fn main() {\n    run();\n}\n").unwrap();
+        fs::write(dir.join("src/build.rs"), "fn main() {}\n").unwrap();
+        fs::write(dir.join("README.md"), "# enforcer\n").unwrap();
+        fs::write(dir.join("build_x86/gen.c"), "\tint x;\r\n").unwrap();
+        fs::write(dir.join("src/node_modules/lib/x.js"), "\tx();\n").unwrap();
+
+        let survey = Survey::of(&dir);
+        assert_eq!(
+            vec!["**/.git", "**/build_x86", "**/node_modules", "**/target"],
+            survey.ignore.iter().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![".md", ".rs"],
+            survey.endings.keys().collect::<Vec<_>>()
+        );
+        assert!(!survey.allow_tabs());
+        assert_eq!(LineEnding::LF, survey.line_ending());
+
+        let cfg = parse_config(&survey.config()).unwrap();
+        assert_eq!(vec![".md", ".rs"], cfg.endings);
+        assert_eq!(
+            vec!["**/.git", "**/build_x86", "**/node_modules", "**/target"],
+            cfg.ignore
+        );
+        assert!(!cfg.rules.allow_tabs);
+        assert_eq!(Some(80), cfg.rules.max_line_length);
+        assert!(survey.config().contains("\nallow_tabs = false\n"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
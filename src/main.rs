@@ -4,17 +4,19 @@ extern crate log;
 extern crate ansi_term;
 extern crate env_logger;
 extern crate pbr;
+extern crate serde;
 extern crate serde_derive;
 extern crate term_painter;
 #[macro_use]
 extern crate clap;
 extern crate anyhow;
 extern crate glob;
+extern crate ignore;
 extern crate num_cpus;
 extern crate regex;
+extern crate serde_json;
 extern crate toml;
 extern crate unic_char_range;
-extern crate walkdir;
 
 use args::Args;
 
@@ -23,13 +25,19 @@ mod args;
 mod check;
 mod clean;
 mod config;
+mod editorconfig;
+mod git;
+mod init;
+mod report;
 mod search;
+mod syntax;
 
 use pbr::ProgressBar;
 use std::{
     fs::File,
-    io::prelude::*,
-    num, process,
+    io::{self, prelude::*},
+    path::{Path, PathBuf},
+    process,
     sync::{
         mpsc::{sync_channel, SyncSender},
         Arc,
@@ -46,7 +54,11 @@ macro_rules! eprintln {
 
 #[allow(dead_code)]
 fn main() {
-    match Args::parse().map(Arc::new).and_then(run) {
+    match Args::parse()
+        .map_err(anyhow::Error::from)
+        .map(Arc::new)
+        .and_then(run)
+    {
         Ok(0) => process::exit(0),
         Ok(_) => process::exit(1),
         Err(err) => {
@@ -56,58 +68,85 @@ fn main() {
     }
 }
 
-fn run(args: Arc<Args>) -> Result<u64, num::ParseIntError> {
-    let enforcer_cfg = config::get_cfg(args.config_file());
+fn run(args: Arc<Args>) -> Result<u64, anyhow::Error> {
+    match args.command() {
+        Some(args::Command::Migrate { path, check }) => {
+            return migrate_configs(path, args.config_file().as_deref(), *check)
+        }
+        Some(args::Command::Init { path, force }) => return init_config(path, *force),
+        None => (),
+    }
+    let cli = args.clone();
+    let cfg_tree = Arc::new(config::CfgTree::new(
+        args.config_file().as_deref(),
+        args.config_fallback(),
+        move |cfg| effective_cfg(cfg, &cli),
+    )?);
+    let start_paths = args.paths();
+    let start_cfg = cfg_tree.cfg_in(git::dir_of(&start_paths[0]));
     if args.status() {
-        println!("  using this config: {:?}", enforcer_cfg);
+        cfg_tree.check()?;
+        for f in &start_cfg.files {
+            println!("# {}", f.display());
+        }
+        print!("{}", toml::to_string_pretty(&*start_cfg)?);
         std::process::exit(0);
     }
-    let cfg_ignores: &Vec<String> = &enforcer_cfg.ignore;
-    let cfg_endings = enforcer_cfg.endings;
-    let file_endings = if !args.endings().is_empty() {
-        args.endings()
-    } else {
-        &cfg_endings
-    };
+    let selection: Arc<dyn search::Selection> = cfg_tree.clone();
+    let editorconfigs = editorconfig::EditorConfigs::default();
 
-    let mut checked_files: u32 = 0;
-    let mut had_tabs: u32 = 0;
-    let mut had_trailing_ws: u32 = 0;
-    let mut had_illegals: u32 = 0;
-    let mut had_too_long_lines: u32 = 0;
-    let mut had_win_line_endings: u32 = 0;
+    let mut findings = report::Findings::default();
     let clean_f = args.clean();
-    let tabs_f = args.tabs();
-    let use_crlf = args.use_crlf();
-    let thread_count = args.threads();
-    let color_f = args.color();
-    let max_line_length = args.line_length();
-    let start_dir = args.path();
+    let thread_count = start_cfg.run.thread_count();
+    let color_f = start_cfg.run.color;
+    let format = args.format();
     debug!("args:{:?}", args);
     if args.quiet() {
-        println!("quiet flag was used but is deprecated...use verbosity instead");
+        eprintln!("quiet flag was used but is deprecated...use verbosity instead");
     }
     let info_level: check::InfoLevel = args.info_level();
-    let paths = search::find_matches(start_dir.as_path(), cfg_ignores, file_endings);
+    if args.stdin() {
+        let name = args
+            .stdin_filename()
+            .unwrap_or_else(|| Path::new("<stdin>"));
+        let opts = file_options(&cfg_tree, &editorconfigs, name, clean_f, info_level);
+        cfg_tree.check()?;
+        return check_stdin(name, &opts, format, color_f);
+    }
+    let show_progress = info_level == check::InfoLevel::Quiet && !format.is_machine_readable();
+    let staged = args.staged();
+    let mut paths = if let Some(list) = args.files_from() {
+        search::dedup(search::filter_matches(read_file_list(list)?, &*selection))
+    } else if staged {
+        staged_matches(start_paths, &*selection)?
+    } else {
+        search::collect_matches(start_paths, selection, args.use_ignore_files())
+    };
+    cfg_tree.check()?;
+    let changed_lines = match args.diff_base() {
+        Some(base) => {
+            let changed = changed_lines(start_paths, base, staged)?;
+            paths.retain(|p| changed.contains_key(&search::canonical(p)));
+            Some(changed)
+        }
+        None => None,
+    };
+    let clean_changed_only = args.clean_changed_only();
     let count: u64 = paths.len() as u64;
     let mut pb = ProgressBar::new(count);
     // logger thread
     let (logging_tx, logging_rx) = sync_channel::<Option<String>>(0);
     let stop_logging_tx = logging_tx.clone();
-    thread::spawn(move || {
-        let mut done = false;
-        while !done {
-            done = logging_rx
-                .recv()
-                .ok()
-                // not done when we got a receive error (sender end of connection closed)
-                .map_or(false, |maybe_print| {
-                    maybe_print
-                        // a None indicates that logging is done
-                        .map_or(true, |p|
-                                // just print the string we received
-                                {print!("{}", p); false})
-                });
+    let logger = thread::spawn(move || {
+        // a None (or a closed channel) indicates that logging is done
+        while let Ok(Some(p)) = logging_rx.recv() {
+            // just print the string we received (stdout belongs to the report
+            // for machine readable formats)
+            if format.is_machine_readable() {
+                eprint!("{}", p);
+            } else {
+                print!("{}", p);
+            }
         }
     });
 
@@ -118,36 +157,33 @@ fn run(args: Arc<Args>) -> Result<u64, num::ParseIntError> {
 
         pool.scoped(|scope| {
             for path in paths {
-                let ch: SyncSender<Result<u8, std::io::Error>> = w_chan.clone();
+                let ch: SyncSender<(PathBuf, io::Result<Vec<check::Finding>>)> = w_chan.clone();
                 let l_ch: SyncSender<Option<String>> = logging_tx.clone();
+                let file_opts = file_options(&cfg_tree, &editorconfigs, &path, clean_f, info_level);
+                let line_filter = changed_lines.as_ref().map(|changed| check::LineFilter {
+                    ranges: changed
+                        .get(&search::canonical(&path))
+                        .map_or(&[], |r| r.as_slice()),
+                    restrict_clean: clean_changed_only,
+                });
                 scope.execute(move || {
-                    if !check::is_dir(path.as_path()) {
+                    if staged || !check::is_dir(path.as_path()) {
                         let p = path.clone();
-                        let mut f = File::open(path)
-                            .unwrap_or_else(|_| panic!("error reading file {:?}", p));
-                        let mut buffer = Vec::new();
-                        f.read_to_end(&mut buffer)
-                            .unwrap_or_else(|_| panic!("error reading file {:?}", p));
+                        let buffer = if staged {
+                            git::staged_content(&p)
+                        } else {
+                            File::open(path)
+                                .and_then(|mut f| {
+                                    let mut buffer = Vec::new();
+                                    f.read_to_end(&mut buffer).map(|_| buffer)
+                                })
+                                .map_err(anyhow::Error::from)
+                        }
+                        .unwrap_or_else(|e| panic!("error reading file {:?}: {}", p, e));
 
-                        let r = check::check_path(
-                            p.as_path(),
-                            &buffer,
-                            clean_f,
-                            info_level,
-                            max_line_length,
-                            if tabs_f {
-                                clean::TabStrategy::Tabify
-                            } else {
-                                clean::TabStrategy::Untabify
-                            },
-                            if use_crlf {
-                                clean::LineEnding::CRLF
-                            } else {
-                                clean::LineEnding::LF
-                            },
-                            l_ch,
-                        );
-                        ch.send(r).expect("send result with SyncSender");
+                        let r =
+                            check::check_path(p.as_path(), &buffer, &file_opts, line_filter, l_ch);
+                        ch.send((p, r)).expect("send result with SyncSender");
                     }
                 });
             }
@@ -155,24 +191,8 @@ fn run(args: Arc<Args>) -> Result<u64, num::ParseIntError> {
     });
     for _ in 0..count {
         match r_chan.recv() {
-            Ok(res) => match res {
-                Ok(r) => {
-                    if (r & check::HAS_TABS) > 0 {
-                        had_tabs += 1
-                    }
-                    if (r & check::TRAILING_SPACES) > 0 {
-                        had_trailing_ws += 1
-                    }
-                    if (r & check::HAS_ILLEGAL_CHARACTERS) > 0 {
-                        had_illegals += 1
-                    }
-                    if (r & check::LINE_TOO_LONG) > 0 {
-                        had_too_long_lines += 1
-                    }
-                    if (r & check::HAS_WINDOWS_LINE_ENDINGS) > 0 {
-                        had_win_line_endings += 1
-                    }
-                }
+            Ok((path, res)) => match res {
+                Ok(file_findings) => findings.add(path, file_findings),
                 Err(e) => {
                     error!("error occured here: {}", e);
                 }
@@ -181,95 +201,368 @@ fn run(args: Arc<Args>) -> Result<u64, num::ParseIntError> {
                 panic!("error in channel: {}", e);
             }
         }
-        checked_files += 1;
-        if info_level == check::InfoLevel::Quiet {
+        findings.checked_files += 1;
+        if show_progress {
             pb.inc();
         }
     }
-    if info_level == check::InfoLevel::Quiet {
+    if show_progress {
         pb.finish();
     };
     let _ = stop_logging_tx.send(None);
-    let findings = Findings {
-        had_tabs,
-        had_trailing_ws,
-        had_illegals,
-        had_too_long_lines,
-        had_win_line_endings,
-        checked_files,
+    let _ = logger.join();
+    Ok(report::report(
+        format,
+        info_level == check::InfoLevel::Quiet,
+        findings,
+        color_f,
+    )?)
+}
+
+// rewrite the old style ignore patterns of the config files, with `check`
+// nothing is written and the number of outdated files is returned
+fn migrate_configs(
+    path: &Path,
+    config_file: Option<&Path>,
+    check: bool,
+) -> Result<u64, anyhow::Error> {
+    let files = match config_file {
+        Some(file) => vec![file.to_path_buf()],
+        None if path.is_file() => vec![path.to_path_buf()],
+        None => config::config_files(&search::resolved(path).unwrap_or_else(|| path.into())),
     };
-    report_findings(info_level == check::InfoLevel::Quiet, findings, color_f)
+    if files.is_empty() {
+        return Err(anyhow::anyhow!(
+            "no {} file found in {} or above",
+            config::CFG_FILE_NAME,
+            path.display()
+        ));
+    }
+    let mut outdated = 0;
+    for file in &files {
+        let content = std::fs::read_to_string(file)
+            .map_err(|e| anyhow::anyhow!("{}: {}", file.display(), e))?;
+        match config::migrate(&content).map_err(|e| anyhow::anyhow!("{}: {}", file.display(), e))? {
+            Some(migrated) => {
+                print!("{}", config::migration_diff(file, &content, &migrated));
+                if !check {
+                    std::fs::write(file, migrated)?;
+                }
+                outdated += 1;
+            }
+            None => println!("{} is up to date", file.display()),
+        }
+    }
+    Ok(if check { outdated } else { 0 })
 }
 
-#[derive(Debug)]
-struct Findings {
-    had_tabs: u32,
-    had_trailing_ws: u32,
-    had_illegals: u32,
-    had_too_long_lines: u32,
-    had_win_line_endings: u32,
-    checked_files: u32,
+// propose a config file for the files found in `dir`
+fn init_config(dir: &Path, force: bool) -> Result<u64, anyhow::Error> {
+    let file = dir.join(config::CFG_FILE_NAME);
+    if file.exists() && !force {
+        return Err(anyhow::anyhow!(
+            "{} already exists (use --force to overwrite it)",
+            file.display()
+        ));
+    }
+    let survey = init::Survey::of(dir);
+    if survey.endings.is_empty() {
+        return Err(anyhow::anyhow!("no text files found in {}", dir.display()));
+    }
+    let cfg = survey.config();
+    std::fs::write(&file, &cfg).map_err(|e| anyhow::anyhow!("{}: {}", file.display(), e))?;
+    print!("{}", cfg);
+    eprintln!("wrote {}", file.display());
+    Ok(0)
 }
 
-fn report_findings(
-    quiet: bool,
-    findings: Findings,
-    colored: bool,
-) -> Result<u64, num::ParseIntError> {
-    let total_errors = findings.had_tabs
-        + findings.had_illegals
-        + findings.had_trailing_ws
-        + findings.had_too_long_lines
-        + findings.had_win_line_endings;
-    if quiet {
-        if colored {
-            println!("{}: {}", check::bold("enforcer-error-count"), total_errors);
-        } else {
-            println!("enforcer-error-count: {}", total_errors);
-        }
+// the config with the command line options applied, they take precedence
+fn effective_cfg(mut cfg: config::EnforcerCfg, args: &Args) -> config::EnforcerCfg {
+    if !args.endings().is_empty() {
+        cfg.endings = args.endings().to_vec();
+    }
+    if let Some(width) = args.tab_width() {
+        cfg.tab_width = Some(width);
+        cfg.tab_widths.clear();
+    }
+    if args.line_length().is_some() {
+        cfg.rules.max_line_length = args.line_length();
     }
-    if total_errors > 0 {
-        if colored {
-            println!(
-                "checked {} files {}",
-                findings.checked_files,
-                check::bold("(enforcer_errors!)")
-            );
-        } else {
-            println!(
-                "checked {} files (enforcer_errors!)",
-                findings.checked_files
-            );
+    if args.tabs() {
+        cfg.rules.allow_tabs = true;
+    }
+    if args.use_crlf() {
+        cfg.rules.line_ending = clean::LineEnding::CRLF;
+    }
+    if let Some(ending) = args.line_ending() {
+        cfg.rules.line_ending = ending;
+    }
+    if let Some(threads) = args.threads() {
+        cfg.run.threads = threads;
+    }
+    if args.color() {
+        cfg.run.color = true;
+    }
+    if args.editorconfig() {
+        cfg.rules.editorconfig = true;
+    }
+    cfg
+}
+
+fn tab_strategy(allow_tabs: bool, smart_tabs: bool) -> clean::TabStrategy {
+    match (allow_tabs, smart_tabs) {
+        (true, true) => clean::TabStrategy::SmartTabs,
+        (true, false) => clean::TabStrategy::Tabify,
+        (false, _) => clean::TabStrategy::Untabify,
+    }
+}
+
+// the options for a single file from the config of its directory, with the
+// EditorConfig properties (if enabled) and all matching override sections applied
+fn file_options(
+    cfg_tree: &config::CfgTree,
+    editorconfigs: &editorconfig::EditorConfigs,
+    path: &Path,
+    clean: bool,
+    info_level: check::InfoLevel,
+) -> check::CheckOptions {
+    let (cfg, rel) = cfg_tree.lookup(path);
+    let rules = &cfg.rules;
+    let mut res = check::CheckOptions {
+        clean,
+        info_level,
+        max_line_length: rules.max_line_length,
+        indent_width: rules.indent_width,
+        tab_strategy: tab_strategy(rules.allow_tabs, rules.smart_tabs),
+        tab_width: cfg.tab_width_for(path),
+        line_ending: rules.line_ending,
+        bom: rules.bom,
+        disabled_rules: rules.disabled_rules(),
+    };
+    if rules.editorconfig {
+        apply_editorconfig(&mut res, &editorconfigs.properties_for(path));
+    }
+    for o in cfg.overrides_for(&rel) {
+        let tabs = o.tabs_allowed.map(|allowed| (check::Rule::Tabs, !allowed));
+        let toggled = o
+            .enable
+            .iter()
+            .map(|r| (*r, true))
+            .chain(o.disable.iter().map(|r| (*r, false)))
+            .chain(tabs);
+        for (rule, enabled) in toggled {
+            res.set_enabled(rule, enabled);
         }
-        if findings.had_tabs > 0 {
-            println!("   [with TABS:{}]", findings.had_tabs)
+        if let Some(allowed) = o.tabs_allowed {
+            res.tab_strategy = tab_strategy(allowed, rules.smart_tabs);
         }
-        if findings.had_illegals > 0 {
-            println!("   [with ILLEGAL CHARS:{}]", findings.had_illegals)
+        if o.max_line_length.is_some() {
+            res.max_line_length = o.max_line_length;
         }
-        if findings.had_trailing_ws > 0 {
-            println!("   [with TRAILING SPACES:{}]", findings.had_trailing_ws)
+        if o.indent_width.is_some() {
+            res.indent_width = o.indent_width;
         }
-        if findings.had_too_long_lines > 0 {
-            println!("   [with TOO LONG LINES:{}]", findings.had_too_long_lines)
+        if let Some(ending) = o.line_ending {
+            res.line_ending = ending;
         }
-        if findings.had_win_line_endings > 0 {
-            println!(
-                "   [with WINDOWS LINE ENDINGS:{}]",
-                findings.had_win_line_endings
-            )
+        if let Some(bom) = o.bom {
+            res.bom = bom;
         }
-        Ok(1)
+    }
+    res
+}
+
+fn apply_editorconfig(opts: &mut check::CheckOptions, props: &editorconfig::Properties) {
+    if let Some(style) = props.indent_style {
+        opts.tab_strategy = match (style, opts.tab_strategy) {
+            (editorconfig::IndentStyle::Tab, clean::TabStrategy::SmartTabs) => {
+                clean::TabStrategy::SmartTabs
+            }
+            (editorconfig::IndentStyle::Tab, _) => clean::TabStrategy::Tabify,
+            (editorconfig::IndentStyle::Space, _) => clean::TabStrategy::Untabify,
+        };
+    }
+    if let Some(width) = props.tab_width {
+        opts.tab_width = width;
+    }
+    if let Some(width) = props.indent_size {
+        opts.indent_width = Some(width);
+    }
+    if let Some(ending) = props.end_of_line {
+        opts.line_ending = ending;
+    }
+    if let Some(trim) = props.trim_trailing_whitespace {
+        opts.set_enabled(check::Rule::TrailingSpaces, trim);
+    }
+    if let Some(bom) = props.charset {
+        opts.bom = bom;
+    }
+    if let Some(insert) = props.insert_final_newline {
+        opts.set_enabled(check::Rule::MissingFinalNewline, insert);
+    }
+    if let Some(max_line_length) = props.max_line_length {
+        opts.max_line_length = max_line_length;
+    }
+}
+
+// check the content piped on stdin, when cleaning the cleaned content is
+// written to stdout instead of a report
+fn check_stdin(
+    name: &Path,
+    opts: &check::CheckOptions,
+    format: report::Format,
+    color_f: bool,
+) -> Result<u64, anyhow::Error> {
+    let mut buffer = Vec::new();
+    io::stdin().read_to_end(&mut buffer)?;
+    let (logging_tx, logging_rx) = sync_channel::<Option<String>>(0);
+    let logger = thread::spawn(move || {
+        while let Ok(Some(p)) = logging_rx.recv() {
+            eprint!("{}", p);
+        }
+    });
+    let (file_findings, cleaned) = check::check_buffer(name, &buffer, opts, None, &logging_tx);
+    let _ = logging_tx.send(None);
+    let _ = logger.join();
+    if opts.clean {
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        match cleaned {
+            Some(content) => out.write_all(content.as_bytes())?,
+            None => out.write_all(&buffer)?,
+        }
+        out.flush()?;
+        return Ok(0);
+    }
+    let mut findings = report::Findings::default();
+    findings.add(name.to_path_buf(), file_findings);
+    findings.checked_files = 1;
+    Ok(report::report(
+        format,
+        opts.info_level == check::InfoLevel::Quiet,
+        findings,
+        color_f,
+    )?)
+}
+
+// the files listed in a file (or on stdin for "-")
+fn read_file_list(list: &Path) -> Result<Vec<PathBuf>, anyhow::Error> {
+    let mut content = Vec::new();
+    if list == Path::new("-") {
+        io::stdin().read_to_end(&mut content)?;
     } else {
-        if colored {
-            println!(
-                "checked {} files {}",
-                findings.checked_files,
-                check::green("(enforcer_clean!)")
-            );
-        } else {
-            println!("checked {} files (enforcer_clean!)", findings.checked_files);
+        File::open(list)
+            .and_then(|mut f| f.read_to_end(&mut content))
+            .map_err(|e| anyhow::anyhow!("could not read file list {:?}: {}", list, e))?;
+    }
+    Ok(search::parse_file_list(&content)
+        .into_iter()
+        .filter(|p| {
+            let exists = p.is_file();
+            if !exists {
+                eprintln!("listed file {:?} does not exist", p);
+            }
+            exists
+        })
+        .collect())
+}
+
+// the staged files for all given paths: directories contribute all their
+// staged files with a matching ending, named files are taken if they are staged
+fn staged_matches(
+    start_paths: &[PathBuf],
+    selection: &dyn search::Selection,
+) -> Result<Vec<PathBuf>, anyhow::Error> {
+    let mut files = Vec::new();
+    for p in start_paths {
+        let staged = git::staged_files(git::dir_of(p))?;
+        if p.is_dir() {
+            files.extend(search::filter_matches(staged, selection));
+        } else if !selection.is_ignored_path(p)
+            && staged
+                .iter()
+                .any(|s| search::canonical(s) == search::canonical(p))
+        {
+            files.push(p.clone());
+        }
+    }
+    Ok(search::dedup(files))
+}
+
+// changed lines for all given paths, keyed by canonical path
+fn changed_lines(
+    start_paths: &[PathBuf],
+    base: &str,
+    staged: bool,
+) -> Result<git::ChangedLines, anyhow::Error> {
+    let mut res = git::ChangedLines::new();
+    for p in start_paths {
+        for (file, ranges) in git::changed_lines(git::dir_of(p), base, staged)? {
+            res.insert(search::canonical(&file), ranges);
         }
-        Ok(0)
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::file_options;
+    use crate::{
+        check::{InfoLevel, Rule},
+        clean::TabStrategy,
+        config::CfgTree,
+        editorconfig::EditorConfigs,
+    };
+    use std::{fs, path::Path};
+
+    #[test]
+    fn test_override_tabs_allowed() {
+        let dir =
+            std::env::temp_dir().join(format!("enforcer_options_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let options = |cfg: &str, file: &str| {
+            let cfg_file = dir.join(".enforcer");
+            fs::write(&cfg_file, cfg).unwrap();
+            let tree = CfgTree::new(Some(&cfg_file), false, |cfg| cfg).unwrap();
+            let editorconfigs = EditorConfigs::default();
+            file_options(
+                &tree,
+                &editorconfigs,
+                Path::new(file),
+                false,
+                InfoLevel::Quiet,
+            )
+        };
+
+        let tabs = r#"
+            ignore = []
+            endings = [".c", ".py"]
+            [rules]
+            allow_tabs = true
+            [[override]]
+            endings = [".py"]
+            tabs_allowed = false
+        "#;
+        let py = options(tabs, "a.py");
+        assert_eq!(py.tab_strategy, TabStrategy::Untabify);
+        assert!(py.is_enabled(Rule::Tabs));
+        assert_eq!(options(tabs, "a.c").tab_strategy, TabStrategy::Tabify);
+
+        let spaces = r#"
+            ignore = []
+            endings = [".c", "Makefile"]
+            [rules]
+            allow_tabs = false
+            smart_tabs = true
+            [[override]]
+            endings = ["Makefile"]
+            tabs_allowed = true
+        "#;
+        let make = options(spaces, "Makefile");
+        assert_eq!(make.tab_strategy, TabStrategy::SmartTabs);
+        assert!(!make.is_enabled(Rule::Tabs));
+        assert_eq!(options(spaces, "a.c").tab_strategy, TabStrategy::Untabify);
+        let _ = fs::remove_dir_all(&dir);
     }
 }
@@ -0,0 +1,692 @@
+use serde_json::json;
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    num,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::check;
+
+/// The output formats of the final report.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+    Sarif,
+    Junit,
+    Checkstyle,
+    Github,
+    Gitlab,
+}
+
+impl Format {
+    pub const NAMES: &'static [&'static str] = &[
+        "text",
+        "json",
+        "sarif",
+        "junit",
+        "checkstyle",
+        "github",
+        "gitlab",
+    ];
+
+    /// Machine readable formats own stdout: progress and log output go elsewhere.
+    pub fn is_machine_readable(self) -> bool {
+        self != Format::Text
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "sarif" => Ok(Format::Sarif),
+            "junit" => Ok(Format::Junit),
+            "checkstyle" => Ok(Format::Checkstyle),
+            "github" => Ok(Format::Github),
+            "gitlab" => Ok(Format::Gitlab),
+            _ => Err(format!("unknown format: {}", s)),
+        }
+    }
+}
+
+/// Summary of the findings of all checked files.
+#[derive(Debug, Default)]
+pub struct Findings {
+    findings: Vec<check::Finding>,
+    /// all files that could be checked
+    files: Vec<PathBuf>,
+    pub checked_files: u32,
+    /// number of files with at least one violation
+    files_with_errors: u32,
+    /// number of files with at least one violation of a rule
+    files_per_rule: BTreeMap<check::Rule, u32>,
+    /// number of violations of a rule over all files
+    violations_per_rule: BTreeMap<check::Rule, u32>,
+}
+
+impl Findings {
+    pub fn add(&mut self, path: PathBuf, file_findings: Vec<check::Finding>) {
+        self.files.push(path);
+        if !file_findings.is_empty() {
+            self.files_with_errors += 1;
+        }
+        for rule in check::Rule::ALL {
+            let violations = file_findings.iter().filter(|f| f.rule == *rule).count() as u32;
+            if violations > 0 {
+                *self.files_per_rule.entry(*rule).or_insert(0) += 1;
+                *self.violations_per_rule.entry(*rule).or_insert(0) += violations;
+            }
+        }
+        self.findings.extend(file_findings);
+    }
+
+    /// all findings, ordered by file, line and column
+    fn sorted(&self) -> Vec<&check::Finding> {
+        let mut all: Vec<&check::Finding> = self.findings.iter().collect();
+        all.sort_by(|a, b| (&a.path, a.line, a.column).cmp(&(&b.path, b.line, b.column)));
+        all
+    }
+
+    /// all checked files (ordered) with their findings
+    fn by_file(&self) -> BTreeMap<&Path, Vec<&check::Finding>> {
+        let mut files: BTreeMap<&Path, Vec<&check::Finding>> =
+            self.files.iter().map(|p| (p.as_path(), vec![])).collect();
+        for f in self.sorted() {
+            files.entry(f.path.as_path()).or_default().push(f);
+        }
+        files
+    }
+
+    /// number of files affected by a rule
+    fn files(&self, rule: check::Rule) -> u32 {
+        self.files_per_rule.get(&rule).cloned().unwrap_or(0)
+    }
+
+    fn violations(&self) -> u32 {
+        self.violations_per_rule.values().sum()
+    }
+}
+
+fn report_findings(
+    quiet: bool,
+    findings: Findings,
+    colored: bool,
+) -> Result<u64, num::ParseIntError> {
+    let total_errors: u32 = findings.files_per_rule.values().sum();
+    if quiet {
+        if colored {
+            println!("{}: {}", check::bold("enforcer-error-count"), total_errors);
+        } else {
+            println!("enforcer-error-count: {}", total_errors);
+        }
+    }
+    if total_errors > 0 {
+        if colored {
+            println!(
+                "checked {} files {}",
+                findings.checked_files,
+                check::bold("(enforcer_errors!)")
+            );
+        } else {
+            println!(
+                "checked {} files (enforcer_errors!)",
+                findings.checked_files
+            );
+        }
+        for rule in check::Rule::ALL {
+            if findings.files(*rule) > 0 {
+                println!("   [with {}:{}]", rule.label(), findings.files(*rule))
+            }
+        }
+        println!(
+            "   {} violations in {} files",
+            findings.violations(),
+            findings.files_with_errors
+        );
+        Ok(1)
+    } else {
+        if colored {
+            println!(
+                "checked {} files {}",
+                findings.checked_files,
+                check::green("(enforcer_clean!)")
+            );
+        } else {
+            println!("checked {} files (enforcer_clean!)", findings.checked_files);
+        }
+        Ok(0)
+    }
+}
+
+pub fn report(
+    format: Format,
+    quiet: bool,
+    findings: Findings,
+    colored: bool,
+) -> Result<u64, num::ParseIntError> {
+    match format {
+        Format::Text => report_findings(quiet, findings, colored),
+        Format::Json => report_json(&findings),
+        Format::Sarif => report_sarif(&findings),
+        Format::Junit => {
+            print!("{}", junit_xml(&findings));
+            exit_code(&findings)
+        }
+        Format::Checkstyle => {
+            print!("{}", checkstyle_xml(&findings));
+            exit_code(&findings)
+        }
+        Format::Github => {
+            print!("{}", github_annotations(&findings));
+            exit_code(&findings)
+        }
+        Format::Gitlab => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&gitlab_code_quality(&findings))
+                    .expect("serializable report")
+            );
+            exit_code(&findings)
+        }
+    }
+}
+
+fn exit_code(findings: &Findings) -> Result<u64, num::ParseIntError> {
+    Ok(if findings.findings.is_empty() { 0 } else { 1 })
+}
+
+// path with forward slashes and without a leading "./"
+fn normalized(path: &Path) -> String {
+    let p = path.to_string_lossy().replace('\\', "/");
+    p.strip_prefix("./").map(String::from).unwrap_or(p)
+}
+
+// paths as URIs with forward slashes (relative ones stay relative)
+fn uri(path: &Path) -> String {
+    let p = normalized(path);
+    if path.is_absolute() {
+        format!("file://{}{}", if p.starts_with('/') { "" } else { "/" }, p)
+    } else {
+        p
+    }
+}
+
+fn report_json(findings: &Findings) -> Result<u64, num::ParseIntError> {
+    let rules: serde_json::Map<String, serde_json::Value> = check::Rule::ALL
+        .iter()
+        .map(|rule| {
+            (
+                rule.id().to_string(),
+                json!({
+                    "files": findings.files(*rule),
+                    "violations": findings.violations_per_rule.get(rule).cloned().unwrap_or(0),
+                }),
+            )
+        })
+        .collect();
+    let doc = json!({
+        "version": crate_version!(),
+        "summary": {
+            "checked_files": findings.checked_files,
+            "files_with_errors": findings.files_with_errors,
+            "violations": findings.violations(),
+            "rules": rules,
+        },
+        "findings": findings
+            .sorted()
+            .iter()
+            .map(|f| json!({
+                "file": normalized(&f.path),
+                "line": f.line,
+                "column": f.column,
+                "rule": f.rule.id(),
+                "message": f.message,
+                "severity": f.severity.to_string(),
+            }))
+            .collect::<Vec<_>>(),
+    });
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&doc).expect("serializable report")
+    );
+    exit_code(findings)
+}
+
+fn sarif_log(findings: &Findings) -> serde_json::Value {
+    let rules: Vec<serde_json::Value> = check::Rule::ALL
+        .iter()
+        .map(|rule| {
+            json!({
+                "id": rule.id(),
+                "shortDescription": { "text": rule.description() },
+                "defaultConfiguration": { "level": "error" },
+            })
+        })
+        .collect();
+    let results: Vec<serde_json::Value> = findings
+        .sorted()
+        .iter()
+        .map(|f| {
+            json!({
+                "ruleId": f.rule.id(),
+                "ruleIndex": check::Rule::ALL.iter().position(|r| *r == f.rule),
+                "level": f.severity.to_string(),
+                "message": { "text": f.message },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": uri(&f.path) },
+                        "region": { "startLine": f.line, "startColumn": f.column },
+                    }
+                }],
+            })
+        })
+        .collect();
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "enforcer",
+                    "version": crate_version!(),
+                    "informationUri": "https://github.com/marcmo/enforcer",
+                    "rules": rules,
+                }
+            },
+            "results": results,
+        }],
+    })
+}
+
+fn report_sarif(findings: &Findings) -> Result<u64, num::ParseIntError> {
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&sarif_log(findings)).expect("serializable report")
+    );
+    exit_code(findings)
+}
+
+// escape text for use in XML attributes and content; characters that are
+// not allowed in XML 1.0 at all are replaced
+fn xml_escape(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => res.push_str("&amp;"),
+            '<' => res.push_str("&lt;"),
+            '>' => res.push_str("&gt;"),
+            '"' => res.push_str("&quot;"),
+            '\'' => res.push_str("&apos;"),
+            '\t' | '\n' | '\r' => res.push(c),
+            c if c < ' ' || c == '\u{fffe}' || c == '\u{ffff}' => res.push('\u{fffd}'),
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+const XML_HEADER: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n";
+
+// every checked file is a testcase, every violated rule a failure of it
+fn junit_xml(findings: &Findings) -> String {
+    let by_file = findings.by_file();
+    let failures: u32 = findings.files_per_rule.values().sum();
+    let mut cases = String::new();
+    for (path, file_findings) in by_file.iter() {
+        let name = xml_escape(&path.display().to_string());
+        if file_findings.is_empty() {
+            let _ = writeln!(
+                cases,
+                "    <testcase name=\"{}\" classname=\"enforcer\"/>",
+                name
+            );
+            continue;
+        }
+        let _ = writeln!(
+            cases,
+            "    <testcase name=\"{}\" classname=\"enforcer\">",
+            name
+        );
+        for rule in check::Rule::ALL {
+            let violations: Vec<&&check::Finding> =
+                file_findings.iter().filter(|f| f.rule == *rule).collect();
+            if violations.is_empty() {
+                continue;
+            }
+            let details: Vec<String> = violations
+                .iter()
+                .map(|f| format!("line {}, column {}: {}", f.line, f.column, f.message))
+                .collect();
+            let _ = writeln!(
+                cases,
+                "      <failure type=\"{}\" message=\"{} ({} violations)\">{}</failure>",
+                rule.id(),
+                xml_escape(rule.description()),
+                violations.len(),
+                xml_escape(&details.join("\n"))
+            );
+        }
+        let _ = writeln!(cases, "    </testcase>");
+    }
+    format!(
+        "{}<testsuites name=\"enforcer\" tests=\"{}\" failures=\"{}\">\n  \
+         <testsuite name=\"enforcer\" tests=\"{}\" failures=\"{}\" errors=\"0\">\n{}  \
+         </testsuite>\n</testsuites>\n",
+        XML_HEADER,
+        by_file.len(),
+        failures,
+        by_file.len(),
+        failures,
+        cases
+    )
+}
+
+fn checkstyle_xml(findings: &Findings) -> String {
+    let mut res = String::from(XML_HEADER);
+    res.push_str("<checkstyle version=\"4.3\">\n");
+    for (path, file_findings) in findings.by_file().iter() {
+        let name = xml_escape(&path.display().to_string());
+        if file_findings.is_empty() {
+            let _ = writeln!(res, "  <file name=\"{}\"/>", name);
+            continue;
+        }
+        let _ = writeln!(res, "  <file name=\"{}\">", name);
+        for f in file_findings {
+            let _ = writeln!(
+                res,
+                "    <error line=\"{}\" column=\"{}\" severity=\"{}\" message=\"{}\" \
+                 source=\"enforcer.{}\"/>",
+                f.line,
+                f.column,
+                f.severity,
+                xml_escape(&f.message),
+                f.rule.id()
+            );
+        }
+        let _ = writeln!(res, "  </file>");
+    }
+    res.push_str("</checkstyle>\n");
+    res
+}
+
+// escaping of workflow command data (and properties when `property` is set)
+fn github_escape(s: &str, property: bool) -> String {
+    let escaped = s
+        .replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A");
+    if property {
+        escaped.replace(':', "%3A").replace(',', "%2C")
+    } else {
+        escaped
+    }
+}
+
+// one workflow command per finding, see
+// https://docs.github.com/actions/using-workflows/workflow-commands-for-github-actions
+fn github_annotations(findings: &Findings) -> String {
+    let mut res = String::new();
+    for f in findings.sorted() {
+        let _ = writeln!(
+            res,
+            "::{} file={},line={},col={},title={}::{}",
+            f.severity,
+            github_escape(&normalized(&f.path), true),
+            f.line,
+            f.column,
+            f.rule.id(),
+            github_escape(&f.message, false)
+        );
+    }
+    res
+}
+
+// 128 bit FNV-1a: a fingerprint that only depends on its input (unlike the
+// std hashers, which are not guaranteed to be stable between releases)
+fn fingerprint(input: &str) -> String {
+    let mut hash: u128 = 0x6c62_272e_07bb_0142_62b8_2175_6295_c58d;
+    for b in input.bytes() {
+        hash ^= u128::from(b);
+        hash = hash.wrapping_mul(0x0000_0000_0100_0000_0000_0000_0000_013b);
+    }
+    format!("{:032x}", hash)
+}
+
+// GitLab Code Quality report, see
+// https://docs.gitlab.com/ee/ci/testing/code_quality.html#implement-a-custom-tool
+// the fingerprint leaves out line and column (so it survives edits elsewhere in
+// the file) and counts findings of the same rule on identical lines instead
+fn gitlab_code_quality(findings: &Findings) -> serde_json::Value {
+    let mut occurrences: BTreeMap<(String, check::Rule, &str), usize> = BTreeMap::new();
+    let issues: Vec<serde_json::Value> = findings
+        .sorted()
+        .iter()
+        .map(|f| {
+            let path = normalized(&f.path);
+            let occurrence = occurrences
+                .entry((path.clone(), f.rule, f.source.as_str()))
+                .or_insert(0);
+            *occurrence += 1;
+            json!({
+                "description": format!("{}: {}", f.rule.id(), f.message),
+                "check_name": f.rule.id(),
+                "fingerprint": fingerprint(&format!(
+                    "{}:{}:{}:{}",
+                    path,
+                    f.rule.id(),
+                    f.source,
+                    occurrence
+                )),
+                "severity": match f.severity {
+                    check::Severity::Error => "major",
+                    check::Severity::Warning => "minor",
+                },
+                "location": {
+                    "path": path,
+                    "lines": { "begin": f.line },
+                },
+            })
+        })
+        .collect();
+    serde_json::Value::Array(issues)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        checkstyle_xml, fingerprint, github_annotations, gitlab_code_quality, junit_xml, sarif_log,
+        uri, xml_escape, Findings, Format,
+    };
+    use crate::check::{Finding, Rule, Severity};
+    use std::path::{Path, PathBuf};
+
+    fn finding(path: &str, line: usize, rule: Rule) -> Finding {
+        Finding {
+            path: PathBuf::from(path),
+            line,
+            column: 1,
+            rule,
+            message: String::from("msg"),
+            severity: Severity::Error,
+            source: format!("line {}", line),
+        }
+    }
+
+    #[test]
+    fn test_parse_format() {
+        assert_eq!("text".parse::<Format>(), Ok(Format::Text));
+        assert_eq!("json".parse::<Format>(), Ok(Format::Json));
+        assert_eq!("sarif".parse::<Format>(), Ok(Format::Sarif));
+        assert_eq!("junit".parse::<Format>(), Ok(Format::Junit));
+        assert_eq!("checkstyle".parse::<Format>(), Ok(Format::Checkstyle));
+        assert_eq!("github".parse::<Format>(), Ok(Format::Github));
+        assert_eq!("gitlab".parse::<Format>(), Ok(Format::Gitlab));
+        assert!("xml".parse::<Format>().is_err());
+    }
+
+    #[test]
+    fn test_github_annotations() {
+        let mut findings = Findings::default();
+        let mut f = finding("./src/a,b.c", 2, Rule::Tabs);
+        f.message = String::from("50% of\nthe line");
+        findings.add(PathBuf::from("./src/a,b.c"), vec![f]);
+        assert_eq!(
+            github_annotations(&findings),
+            "::error file=src/a%2Cb.c,line=2,col=1,title=HAS_TABS::50%25 of%0Athe line\n"
+        );
+    }
+
+    #[test]
+    fn test_gitlab_code_quality() {
+        let mut findings = Findings::default();
+        findings.add(
+            PathBuf::from("./src/a.c"),
+            vec![
+                finding("./src/a.c", 2, Rule::Tabs),
+                finding("./src/a.c", 3, Rule::Tabs),
+            ],
+        );
+        let report = gitlab_code_quality(&findings);
+        let issues = report.as_array().unwrap();
+        assert_eq!(issues.len(), 2);
+        assert_eq!(issues[0]["check_name"], "HAS_TABS");
+        assert_eq!(issues[0]["location"]["path"], "src/a.c");
+        assert_eq!(issues[0]["location"]["lines"]["begin"], 2);
+        assert_ne!(issues[0]["fingerprint"], issues[1]["fingerprint"]);
+        // fingerprints need to be the same for every run
+        assert_eq!(issues, gitlab_code_quality(&findings).as_array().unwrap());
+        assert_eq!(fingerprint(""), "6c62272e07bb014262b821756295c58d");
+        assert_eq!(fingerprint("a"), "d228cb696f1a8caf78912b704e4a8964");
+    }
+
+    #[test]
+    fn test_gitlab_fingerprint_survives_line_shift() {
+        let report = |lines: &[usize]| {
+            let mut findings = Findings::default();
+            let file_findings = lines
+                .iter()
+                .map(|line| {
+                    let mut f = finding("./src/a.c", *line, Rule::Tabs);
+                    f.source = String::from("\tint x;");
+                    f
+                })
+                .collect();
+            findings.add(PathBuf::from("./src/a.c"), file_findings);
+            gitlab_code_quality(&findings)
+        };
+        let before = report(&[2, 5]);
+        let after = report(&[4, 7]);
+        assert_ne!(before[0]["fingerprint"], before[1]["fingerprint"]);
+        assert_eq!(before[0]["fingerprint"], after[0]["fingerprint"]);
+        assert_eq!(before[1]["fingerprint"], after[1]["fingerprint"]);
+        assert_eq!(after[0]["location"]["lines"]["begin"], 4);
+    }
+
+    #[test]
+    fn test_xml_escape() {
+        assert_eq!(
+            xml_escape("a<b>&\"c\" 'd'"),
+            "a&lt;b&gt;&amp;&quot;c&quot; &apos;d&apos;"
+        );
+        assert_eq!(xml_escape("x\u{1}y"), "x\u{fffd}y");
+    }
+
+    #[test]
+    fn test_junit_xml() {
+        let mut findings = Findings::default();
+        findings.add(
+            PathBuf::from("src/a&b.c"),
+            vec![
+                finding("src/a&b.c", 1, Rule::Tabs),
+                finding("src/a&b.c", 2, Rule::Tabs),
+                finding("src/a&b.c", 2, Rule::TrailingSpaces),
+            ],
+        );
+        findings.add(PathBuf::from("src/clean.c"), vec![]);
+        let xml = junit_xml(&findings);
+        assert!(xml.contains("<testsuites name=\"enforcer\" tests=\"2\" failures=\"2\">"));
+        assert!(xml.contains("<testcase name=\"src/a&amp;b.c\" classname=\"enforcer\">"));
+        assert!(xml.contains("<testcase name=\"src/clean.c\" classname=\"enforcer\"/>"));
+        assert_eq!(xml.matches("<failure ").count(), 2);
+        assert!(xml.contains("line 1, column 1: msg\nline 2, column 1: msg</failure>"));
+    }
+
+    #[test]
+    fn test_checkstyle_xml() {
+        let mut findings = Findings::default();
+        findings.add(
+            PathBuf::from("src/<a>.c"),
+            vec![finding("src/<a>.c", 4, Rule::LineTooLong)],
+        );
+        let xml = checkstyle_xml(&findings);
+        assert!(xml.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<checkstyle"));
+        assert!(xml.contains("<file name=\"src/&lt;a&gt;.c\">"));
+        assert!(xml.contains(
+            "<error line=\"4\" column=\"1\" severity=\"error\" message=\"msg\" \
+             source=\"enforcer.LINE_TOO_LONG\"/>"
+        ));
+    }
+
+    #[test]
+    fn test_sarif_results() {
+        let mut findings = Findings::default();
+        findings.add(
+            PathBuf::from("./src/a.c"),
+            vec![finding("./src/a.c", 3, Rule::TrailingSpaces)],
+        );
+        let log = sarif_log(&findings);
+        assert_eq!(log["version"], "2.1.0");
+        let run = &log["runs"][0];
+        let rules = run["tool"]["driver"]["rules"].as_array().unwrap();
+        assert_eq!(rules.len(), Rule::ALL.len());
+        let result = &run["results"][0];
+        assert_eq!(result["ruleId"], "TRAILING_SPACES");
+        assert_eq!(
+            rules[result["ruleIndex"].as_u64().unwrap() as usize]["id"],
+            "TRAILING_SPACES"
+        );
+        let location = &result["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "src/a.c");
+        assert_eq!(location["region"]["startLine"], 3);
+        assert_eq!(location["region"]["startColumn"], 1);
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[test]
+    fn test_uri() {
+        assert_eq!(uri(Path::new("./src/a.c")), "src/a.c");
+        assert_eq!(uri(Path::new("src/a.c")), "src/a.c");
+        assert_eq!(uri(Path::new("/tmp/a.c")), "file:///tmp/a.c");
+    }
+
+    #[test]
+    fn test_count_files_and_violations() {
+        let mut findings = Findings::default();
+        findings.add(
+            PathBuf::from("b.c"),
+            vec![
+                finding("b.c", 3, Rule::Tabs),
+                finding("b.c", 1, Rule::Tabs),
+                finding("b.c", 2, Rule::TrailingSpaces),
+            ],
+        );
+        findings.add(PathBuf::from("c.c"), vec![]);
+        findings.add(PathBuf::from("a.c"), vec![finding("a.c", 7, Rule::Tabs)]);
+        assert_eq!(findings.files_with_errors, 2);
+        assert_eq!(findings.files(Rule::Tabs), 2);
+        assert_eq!(findings.files(Rule::TrailingSpaces), 1);
+        assert_eq!(findings.files(Rule::LineTooLong), 0);
+        assert_eq!(findings.violations(), 4);
+        let order: Vec<(&str, usize)> = findings
+            .sorted()
+            .iter()
+            .map(|f| (f.path.to_str().unwrap(), f.line))
+            .collect();
+        assert_eq!(order, vec![("a.c", 7), ("b.c", 1), ("b.c", 2), ("b.c", 3)]);
+    }
+}
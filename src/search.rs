@@ -1,78 +1,216 @@
 extern crate glob;
-extern crate walkdir;
+extern crate ignore;
 
 use glob::Pattern;
-use std::path;
-use walkdir::{DirEntry, WalkDir};
+use ignore::WalkBuilder;
+use std::{collections::HashSet, path, sync::Arc};
+
+/// Name of the enforcer specific ignore files (gitignore syntax).
+pub const ENFORCER_IGNORE_FILE: &str = ".enforcerignore";
 
 // find out if any path component in the path fully matches the pattern
 fn path_components_matches(pattern: &str, path: &path::Path) -> bool {
     let haystack = path
         .to_str()
         .unwrap_or_else(|| panic!("problems with path: {:?}", &path));
-    let cleaned = if haystack.starts_with("./") {
-        &haystack[2..]
-    } else {
-        haystack
-    };
+    let cleaned = haystack.strip_prefix("./").unwrap_or(haystack);
     match Pattern::new(pattern) {
         Ok(pat) => pat.matches(cleaned),
         Err(e) => {
-            println!("problems with pattern: {:?}({})", pattern, e);
+            eprintln!("problems with pattern: {:?}({})", pattern, e);
             false
         }
     }
 }
 
+/// Whether the path matches one of the glob patterns.
+pub fn matches_any(path: &path::Path, patterns: &[String]) -> bool {
+    patterns
+        .iter()
+        .any(|pattern| path_components_matches(pattern, path))
+}
+
+/// Whether the file name ends with one of the endings (`**/*.c` is the old
+/// way of writing `.c`).
+pub fn has_ending(path: &path::Path, endings: &[String]) -> bool {
+    path.file_name()
+        .and_then(|f| f.to_str())
+        .map(|f| {
+            endings
+                .iter()
+                .any(|e| f.ends_with(e.strip_prefix("**/*").unwrap_or(e)))
+        })
+        .unwrap_or(false)
+}
+
+/// Decides which files are checked. The ignore globs and endings can be
+/// different for every directory.
+pub trait Selection: Send + Sync {
+    /// Whether a file or directory (and everything below it) is ignored.
+    fn is_ignored(&self, path: &path::Path) -> bool;
+    /// Whether a file has one of the endings that are checked.
+    fn has_ending(&self, file: &path::Path) -> bool;
+
+    /// A file is ignored when it or any of its parent directories is.
+    fn is_ignored_path(&self, path: &path::Path) -> bool {
+        path.ancestors().any(|p| self.is_ignored(p))
+    }
+}
+
+/// Walk `start_dir` and collect all selected files. With `use_ignore_files`
+/// the `.gitignore`, `.git/info/exclude`, `.ignore` and `.enforcerignore`
+/// files are honored as well.
 pub fn find_matches(
     start_dir: &path::Path,
-    cfg_ignores: &[String],
-    file_endings: &[String],
+    selection: Arc<dyn Selection>,
+    use_ignore_files: bool,
 ) -> Vec<path::PathBuf> {
-    let walker = WalkDir::new(start_dir).into_iter();
-    let to_ignore = |entry: &DirEntry| -> bool {
-        cfg_ignores
-            .iter()
-            .any(|to_ignore| path_components_matches(to_ignore, entry.path()))
-    };
-    let it = walker
-        .filter_entry(|e| !to_ignore(e))
-        .filter_map(|e| e.ok());
-    let mut res = Vec::new();
-    let endings = file_endings.iter().fold(Vec::new(), |mut acc, ending| {
-        // support old way of writing file endings
-        acc.push(ending.replace("**/*", ""));
-        acc
-    });
-    for f in it {
-        if !f.file_type().is_file() {
-            continue;
-        }
-        if f.file_name()
-            .to_str()
-            .map(|f| endings.iter().any(|p| f.ends_with(p)))
-            .unwrap_or(false)
-        {
-            res.push(f.path().to_owned());
+    let filter = selection.clone();
+    let mut builder = WalkBuilder::new(start_dir);
+    builder
+        .standard_filters(false)
+        .git_ignore(use_ignore_files)
+        .git_exclude(use_ignore_files)
+        .ignore(use_ignore_files)
+        .parents(use_ignore_files)
+        .require_git(false)
+        .filter_entry(move |e| !filter.is_ignored(e.path()));
+    if use_ignore_files {
+        builder.add_custom_ignore_filename(ENFORCER_IGNORE_FILE);
+    }
+    builder
+        .build()
+        .filter_map(|e| e.ok())
+        .filter(|f| f.file_type().is_some_and(|t| t.is_file()))
+        .filter(|f| selection.has_ending(f.path()))
+        .map(|f| f.path().to_owned())
+        .collect()
+}
+
+/// Apply the same selection as `find_matches` to a given list of files: a
+/// file is ignored when it or any of its parent directories is.
+pub fn filter_matches(files: Vec<path::PathBuf>, selection: &dyn Selection) -> Vec<path::PathBuf> {
+    files
+        .into_iter()
+        .filter(|f| selection.has_ending(f))
+        .filter(|f| !selection.is_ignored_path(f))
+        .collect()
+}
+
+/// Parse a list of files, one per line or NUL-separated (as produced by
+/// `git ls-files -z` or `find -print0`).
+pub fn parse_file_list(content: &[u8]) -> Vec<path::PathBuf> {
+    let separator = if content.contains(&0) { 0 } else { b'\n' };
+    content
+        .split(|b| *b == separator)
+        .map(|entry| String::from_utf8_lossy(entry))
+        .map(|entry| {
+            if separator == 0 {
+                entry.to_string()
+            } else {
+                entry.trim_end_matches('\r').to_string()
+            }
+        })
+        .filter(|entry| !entry.is_empty())
+        .map(path::PathBuf::from)
+        .collect()
+}
+
+/// The path with all symlinks and relative components resolved (if it
+/// exists), used to detect the same file given in different ways.
+pub fn canonical(path: &path::Path) -> path::PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// The canonical path of a path that might not exist (yet), only its existing
+/// part is resolved.
+pub fn resolved(path: &path::Path) -> Option<path::PathBuf> {
+    path.ancestors().find_map(|a| {
+        let existing = if a.as_os_str().is_empty() {
+            path::Path::new(".")
+        } else {
+            a
+        };
+        let rest = path.strip_prefix(a).ok()?;
+        existing.canonicalize().ok().map(|c| c.join(rest))
+    })
+}
+
+/// Remove files that are contained more than once (keeping the first).
+pub fn dedup(files: Vec<path::PathBuf>) -> Vec<path::PathBuf> {
+    let mut seen = HashSet::new();
+    files
+        .into_iter()
+        .filter(|f| seen.insert(canonical(f)))
+        .collect()
+}
+
+/// Collect the files to check for all given paths: directories are searched
+/// with `find_matches`, files that were named explicitly are checked
+/// regardless of their ending (unless they are ignored).
+pub fn collect_matches(
+    paths: &[path::PathBuf],
+    selection: Arc<dyn Selection>,
+    use_ignore_files: bool,
+) -> Vec<path::PathBuf> {
+    let mut files = Vec::new();
+    for p in paths {
+        if p.is_dir() {
+            files.extend(find_matches(p, selection.clone(), use_ignore_files));
+        } else if p.is_file() {
+            if !selection.is_ignored_path(p) {
+                files.push(p.clone());
+            }
+        } else {
+            eprintln!("path {:?} does not exist", p);
         }
     }
-    res
+    dedup(files)
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{find_matches, path_components_matches};
-    use std::path;
+    use super::{
+        collect_matches, filter_matches, find_matches, has_ending, matches_any, parse_file_list,
+        path_components_matches, Selection,
+    };
+    use std::{path, sync::Arc};
+
+    // the same ignore globs and endings everywhere
+    struct Globs {
+        ignores: Vec<String>,
+        endings: Vec<String>,
+    }
+
+    impl Selection for Globs {
+        fn is_ignored(&self, path: &path::Path) -> bool {
+            matches_any(path, &self.ignores)
+        }
+        fn has_ending(&self, file: &path::Path) -> bool {
+            has_ending(file, &self.endings)
+        }
+    }
 
     fn s(x: &str) -> String {
         x.to_string()
     }
 
+    fn globs(ignores: &[String], endings: &[String]) -> Arc<Globs> {
+        Arc::new(Globs {
+            ignores: ignores.to_vec(),
+            endings: endings.to_vec(),
+        })
+    }
+
     #[test]
     fn test_find_all_matches() {
         let ignores = vec![s(".git"), s(".bake"), s("**/secret.cpp")];
         let endings = vec![s(".cpp")];
-        let ms = find_matches(path::Path::new("./test/matching"), &ignores, &endings);
+        let ms = find_matches(
+            path::Path::new("./test/matching"),
+            globs(&ignores, &endings),
+            false,
+        );
         assert_eq!(ms.len(), 2);
         assert!(ms.contains(&path::PathBuf::from("./test/matching/test0.cpp")));
         assert!(ms.contains(&path::PathBuf::from("./test/matching/abc/test1.cpp")));
@@ -82,7 +220,11 @@ mod tests {
     fn test_ignore_some_paths() {
         let ignores = vec![s("**/abc/**"), s(".bake"), s("**/secret.cpp")];
         let endings = vec![s(".cpp")];
-        let ms = find_matches(path::Path::new("./test/matching"), &ignores, &endings);
+        let ms = find_matches(
+            path::Path::new("./test/matching"),
+            globs(&ignores, &endings),
+            false,
+        );
         assert_eq!(ms.len(), 1);
         assert!(ms.contains(&path::PathBuf::from("./test/matching/test0.cpp")));
         assert!(!ms.contains(&path::PathBuf::from("./test/matching/secret.cpp")));
@@ -92,7 +234,11 @@ mod tests {
     fn test_ignore_some_paths_with_globs() {
         let ignores = vec![s("**/ab*/**")];
         let endings = vec![s(".cpp")];
-        let ms = find_matches(path::Path::new("./test/matching"), &ignores, &endings);
+        let ms = find_matches(
+            path::Path::new("./test/matching"),
+            globs(&ignores, &endings),
+            false,
+        );
         assert_eq!(ms.len(), 2);
         assert!(ms.contains(&path::PathBuf::from("./test/matching/test0.cpp")));
         assert!(ms.contains(&path::PathBuf::from("./test/matching/secret.cpp")));
@@ -101,44 +247,138 @@ mod tests {
     fn test_ignore_some_paths_with_globs2() {
         let ignores = vec![s("**/a?c/**")];
         let endings = vec![s(".cpp")];
-        let ms = find_matches(path::Path::new("./test/matching"), &ignores, &endings);
+        let ms = find_matches(
+            path::Path::new("./test/matching"),
+            globs(&ignores, &endings),
+            false,
+        );
         assert_eq!(ms.len(), 2);
         assert!(ms.contains(&path::PathBuf::from("./test/matching/test0.cpp")));
         assert!(ms.contains(&path::PathBuf::from("./test/matching/secret.cpp")));
     }
     #[test]
+    fn test_honor_ignore_files() {
+        let ignores = vec![s("**/.git")];
+        let endings = vec![s(".cpp")];
+        let mut ms = find_matches(
+            path::Path::new("./test/ignoring"),
+            globs(&ignores, &endings),
+            true,
+        );
+        ms.sort();
+        assert_eq!(
+            ms,
+            vec![
+                path::PathBuf::from("./test/ignoring/a.cpp"),
+                path::PathBuf::from("./test/ignoring/keep.gen.cpp"),
+                path::PathBuf::from("./test/ignoring/sub/c.cpp"),
+            ]
+        );
+    }
+    #[test]
+    fn test_ignore_files_can_be_disabled() {
+        let ignores = vec![s("**/.git")];
+        let endings = vec![s(".cpp")];
+        let ms = find_matches(
+            path::Path::new("./test/ignoring"),
+            globs(&ignores, &endings),
+            false,
+        );
+        assert_eq!(ms.len(), 6);
+    }
+    #[test]
+    fn test_collect_several_paths() {
+        let ignores = vec![s("**/secret.cpp")];
+        let endings = vec![s(".cpp")];
+        let paths = vec![
+            path::PathBuf::from("./test/matching/abc"),
+            path::PathBuf::from("./test/matching"),
+            path::PathBuf::from("test/matching/test0.cpp"),
+            path::PathBuf::from("./test/withTabs.cpp"),
+            path::PathBuf::from("./test/convertTabs.rb"),
+            path::PathBuf::from("./test/matching/secret.cpp"),
+        ];
+        let mut ms = collect_matches(&paths, globs(&ignores, &endings), false);
+        ms.sort();
+        assert_eq!(
+            ms,
+            vec![
+                path::PathBuf::from("./test/convertTabs.rb"),
+                path::PathBuf::from("./test/matching/abc/test1.cpp"),
+                path::PathBuf::from("./test/matching/test0.cpp"),
+                path::PathBuf::from("./test/withTabs.cpp"),
+            ]
+        );
+    }
+    #[test]
+    fn test_parse_file_list() {
+        let expected = vec![
+            path::PathBuf::from("src/a.c"),
+            path::PathBuf::from("src/with space.c"),
+        ];
+        assert_eq!(parse_file_list(b"src/a.c\nsrc/with space.c\n"), expected);
+        assert_eq!(parse_file_list(b"src/a.c\r\n\nsrc/with space.c"), expected);
+        assert_eq!(parse_file_list(b"src/a.c\0src/with space.c\0"), expected);
+        assert_eq!(
+            parse_file_list(b"new\nline.c\0"),
+            vec![path::PathBuf::from("new\nline.c")]
+        );
+        assert!(parse_file_list(b"").is_empty());
+    }
+    #[test]
+    fn test_filter_given_files() {
+        let ignores = vec![s("**/abc/**"), s("**/.git"), s("**/secret.cpp")];
+        let endings = vec![s(".cpp")];
+        let files = vec![
+            path::PathBuf::from("./test/matching/test0.cpp"),
+            path::PathBuf::from("./test/matching/abc/test1.cpp"),
+            path::PathBuf::from("./test/matching/secret.cpp"),
+            path::PathBuf::from(".git/hooks/x.cpp"),
+            path::PathBuf::from("readme.md"),
+            path::PathBuf::from("src/main.cpp"),
+        ];
+        let ms = filter_matches(files, &*globs(&ignores, &endings));
+        assert_eq!(
+            ms,
+            vec![
+                path::PathBuf::from("./test/matching/test0.cpp"),
+                path::PathBuf::from("src/main.cpp")
+            ]
+        );
+    }
+    #[test]
     fn test_path_component_matches_with_globs() {
         let path = path::Path::new("./test/abc/me.cpp");
-        assert!(path_components_matches("**/a?c/**", &path));
+        assert!(path_components_matches("**/a?c/**", path));
     }
     #[test]
     fn test_path_component_matches_multiple_path_elements() {
         let path = path::Path::new("./test/abc/me.cpp");
-        assert!(path_components_matches("**/test/abc/**", &path));
+        assert!(path_components_matches("**/test/abc/**", path));
     }
     #[test]
     fn test_path_component_matches_full_match() {
         let path = path::Path::new("./test/abc/me.cpp");
-        assert!(path_components_matches("**/a?c/**", &path));
+        assert!(path_components_matches("**/a?c/**", path));
     }
     #[test]
     fn test_path_component_matches_partial_match() {
         let path = path::Path::new("./test/aabcd/me.cpp");
-        assert!(!path_components_matches("**/a?c/**", &path));
+        assert!(!path_components_matches("**/a?c/**", path));
     }
     #[test]
     fn test_path_component_matches_at_begining() {
         let path = path::Path::new("./test/abc/me.cpp");
-        assert!(path_components_matches("**/a*/**", &path));
+        assert!(path_components_matches("**/a*/**", path));
     }
     #[test]
     fn test_path_component_matches_at_end() {
         let path = path::Path::new("./test/abc/me.cpp");
-        assert!(path_components_matches("**/*bc/**", &path));
+        assert!(path_components_matches("**/*bc/**", path));
     }
     #[test]
     fn test_leading_dot_files() {
         let path = path::Path::new("./test/abc/.git");
-        assert!(path_components_matches("**/abc/*", &path));
+        assert!(path_components_matches("**/abc/*", path));
     }
 }
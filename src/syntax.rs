@@ -0,0 +1,242 @@
+use std::path::Path;
+
+// how a block (comment or string literal) ends
+#[derive(Debug, Clone, Copy)]
+enum Close {
+    Text(&'static str),
+    /// C++ raw string `R"delim( ... )delim"`
+    CppRaw,
+    /// Rust raw string `r#" ... "#`
+    RustRaw,
+}
+
+#[derive(Debug)]
+struct Block {
+    open: &'static str,
+    close: Close,
+    /// the block can go on over several lines
+    multiline: bool,
+    /// a backslash escapes the next character
+    escapes: bool,
+}
+
+const fn block(open: &'static str, close: Close, multiline: bool, escapes: bool) -> Block {
+    Block {
+        open,
+        close,
+        multiline,
+        escapes,
+    }
+}
+
+/// The comment and string syntax of some file endings, enough to know which
+/// lines are inside a block comment or a multi-line string.
+#[derive(Debug)]
+struct Syntax {
+    endings: &'static [&'static str],
+    line_comment: &'static str,
+    /// the first one that starts at a position wins
+    blocks: &'static [Block],
+}
+
+const SYNTAXES: &[Syntax] = &[
+    Syntax {
+        endings: &[
+            ".c", ".h", ".cc", ".cpp", ".cxx", ".hh", ".hpp", ".hxx", ".ino",
+        ],
+        line_comment: "//",
+        blocks: &[
+            block("/*", Close::Text("*/"), true, false),
+            block("R\"", Close::CppRaw, true, false),
+            block("\"", Close::Text("\""), false, true),
+            block("'", Close::Text("'"), false, true),
+        ],
+    },
+    Syntax {
+        endings: &[".rs"],
+        line_comment: "//",
+        blocks: &[
+            block("/*", Close::Text("*/"), true, false),
+            block("r", Close::RustRaw, true, false),
+            // the only char literals that could start a string
+            block("'\"'", Close::Text(""), false, false),
+            block("'\\\"'", Close::Text(""), false, false),
+            block("\"", Close::Text("\""), true, true),
+        ],
+    },
+    Syntax {
+        endings: &[".js", ".ts", ".jsx", ".tsx"],
+        line_comment: "//",
+        blocks: &[
+            block("/*", Close::Text("*/"), true, false),
+            block("`", Close::Text("`"), true, true),
+            block("\"", Close::Text("\""), false, true),
+            block("'", Close::Text("'"), false, true),
+        ],
+    },
+    Syntax {
+        endings: &[".py"],
+        line_comment: "#",
+        blocks: &[
+            block("\"\"\"", Close::Text("\"\"\""), true, true),
+            block("'''", Close::Text("'''"), true, true),
+            block("\"", Close::Text("\""), false, true),
+            block("'", Close::Text("'"), false, true),
+        ],
+    },
+];
+
+// an open block: its end and whether it goes on after the line
+struct Open {
+    close: String,
+    multiline: bool,
+    escapes: bool,
+}
+
+impl Block {
+    // the end of the block if it starts at `pos`
+    fn opens(&self, line: &str, pos: usize) -> Option<(usize, Open)> {
+        let rest = &line[pos..];
+        if !rest.starts_with(self.open) {
+            return None;
+        }
+        let (len, close) = match self.close {
+            Close::Text(close) => (self.open.len(), close.to_string()),
+            Close::CppRaw => {
+                let delim = &rest[2..rest.find('(')?];
+                if delim.len() > 16 || delim.contains(|c: char| c.is_whitespace() || c == '"') {
+                    return None;
+                }
+                (delim.len() + 3, format!("){}\"", delim))
+            }
+            Close::RustRaw => {
+                let identifier = line[..pos]
+                    .chars()
+                    .next_back()
+                    .is_some_and(|c| c.is_alphanumeric() || c == '_');
+                let hashes = rest[1..].len() - rest[1..].trim_start_matches('#').len();
+                if identifier || !rest[1 + hashes..].starts_with('"') {
+                    return None;
+                }
+                (hashes + 2, format!("\"{}", "#".repeat(hashes)))
+            }
+        };
+        let open = Open {
+            close,
+            multiline: self.multiline,
+            escapes: self.escapes,
+        };
+        Some((pos + len, open))
+    }
+}
+
+impl Open {
+    // the position after the end of the block
+    fn closes(&self, line: &str, from: usize) -> Option<usize> {
+        let mut chars = line[from..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            if line[from + i..].starts_with(self.close.as_str()) {
+                return Some(from + i + self.close.len());
+            }
+            if self.escapes && c == '\\' {
+                chars.next();
+            }
+        }
+        None
+    }
+}
+
+impl Syntax {
+    fn of(path: &Path) -> Option<&'static Syntax> {
+        let name = path.file_name()?.to_str()?;
+        SYNTAXES
+            .iter()
+            .find(|s| s.endings.iter().any(|e| name.ends_with(e)))
+    }
+
+    // the first block or line comment that starts at `from` or later
+    fn next_open(&self, line: &str, from: usize) -> Option<(usize, Open)> {
+        for (i, _) in line[from..].char_indices() {
+            let pos = from + i;
+            if line[pos..].starts_with(self.line_comment) {
+                return None;
+            }
+            if let Some(found) = self.blocks.iter().find_map(|b| b.opens(line, pos)) {
+                return Some(found);
+            }
+        }
+        None
+    }
+}
+
+/// For every line whether it starts inside a block comment or a multi-line
+/// string literal. Nothing is known about files with other endings.
+pub fn continued_lines(path: &Path, content: &str) -> Vec<bool> {
+    let syntax = match Syntax::of(path) {
+        Some(syntax) => syntax,
+        None => return vec![false; content.lines().count()],
+    };
+    let mut open: Option<Open> = None;
+    let mut res = Vec::new();
+    for line in content.lines() {
+        res.push(open.is_some());
+        let mut pos = 0;
+        loop {
+            match open.take() {
+                Some(block) => match block.closes(line, pos) {
+                    Some(end) => pos = end,
+                    None => {
+                        open = Some(block).filter(|b| b.multiline);
+                        break;
+                    }
+                },
+                None => match syntax.next_open(line, pos) {
+                    Some((end, block)) => {
+                        pos = end;
+                        open = Some(block);
+                    }
+                    None => break,
+                },
+            }
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::continued_lines;
+    use std::path::Path;
+
+    fn continued(name: &str, content: &str) -> Vec<usize> {
+        continued_lines(Path::new(name), content)
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| **c)
+            .map(|(i, _)| i + 1)
+            .collect()
+    }
+
+    #[test]
+    fn test_block_comments() {
+        let content = "/*\n * a\n */\nint x; /* b\n   c */ int y;\n// /* no\nint z;\n";
+        assert_eq!(continued("a.cpp", content), vec![2, 3, 5]);
+        assert_eq!(
+            continued("a.cpp", "char* s = \"/*\";\nint x;\n"),
+            Vec::<usize>::new()
+        );
+        assert_eq!(continued("a.txt", content), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_multiline_strings() {
+        let cpp = "auto s = R\"sql(\n  select *\n)\" )sql\";\nint x;\n";
+        assert_eq!(continued("a.cpp", cpp), vec![2, 3]);
+        let rust = "let s = r#\"\n  \"a\"\n\"#;\nlet c = '\"';\nlet t = \"x\\\"\n y\";\n";
+        assert_eq!(continued("a.rs", rust), vec![2, 3, 6]);
+        let python = "s = \"\"\"\n  a\n\"\"\"\nt = '\"'\nu = 1\n";
+        assert_eq!(continued("a.py", python), vec![2, 3]);
+        let js = "const s = `\n  a ${b}\n`;\n";
+        assert_eq!(continued("a.js", js), vec![2, 3]);
+    }
+}